@@ -0,0 +1,58 @@
+#![feature(test)]
+
+extern crate proof;
+extern crate test;
+
+use proof::ast::{Env, Expr, GlobalEnv};
+use proof::lisp_parse::parse;
+use proof::vm::machine;
+use test::Bencher;
+
+const FIB: &str = "
+  (let fib (defn fib n (if (= n 0) 0 (if (= n 1) 1 (+ (fib (- n 1)) (fib (- n 2)))))))";
+
+const LISTS: &str = "
+  (let build (defn build n acc (if (= n 0) acc (build (- n 1) (cons n acc)))))
+  (let sum (defn sum l acc (if (= l []) acc (sum (tl l) (+ acc (hd l))))))";
+
+fn exprs(src: &str) -> Vec<Expr> {
+  parse(String::from(src)).unwrap().iter().map(|t| t.to_ast().unwrap()).collect()
+}
+
+fn tree_walk(b: &mut Bencher, defs: &str, run: &str) {
+  let mut g_env: GlobalEnv = Env::default_global();
+  for def in exprs(defs) {
+    def.resolve(&mut g_env).unwrap().eval(Env::default(), &mut g_env).unwrap();
+  }
+  let run = exprs(run).remove(0).resolve(&mut g_env).unwrap();
+  b.iter(|| run.eval(Env::default(), &mut g_env).unwrap());
+}
+
+fn bytecode(b: &mut Bencher, defs: &str, run: &str) {
+  let mut g_env: GlobalEnv = Env::default_global();
+  for def in exprs(defs) {
+    machine::eval(&def, &mut g_env).unwrap();
+  }
+  let run = exprs(run).remove(0);
+  b.iter(|| machine::eval(&run, &mut g_env).unwrap());
+}
+
+#[bench]
+fn fib_tree_walk(b: &mut Bencher) {
+  tree_walk(b, FIB, "(fib 15)");
+}
+
+#[bench]
+fn fib_bytecode(b: &mut Bencher) {
+  bytecode(b, FIB, "(fib 15)");
+}
+
+#[bench]
+fn list_sum_tree_walk(b: &mut Bencher) {
+  tree_walk(b, LISTS, "(sum (build 1000 []) 0)");
+}
+
+#[bench]
+fn list_sum_bytecode(b: &mut Bencher) {
+  bytecode(b, LISTS, "(sum (build 1000 []) 0)");
+}
@@ -1,43 +1,90 @@
 use std::sync::Arc;
-use std::ops::Deref;
 use std::borrow::Borrow;
 use std::collections::HashMap;
+use std::sync::RwLock;
+use error::{Arity, RuntimeError};
+use number::Number;
+use hamt::Map;
+use vm;
 
-// A closure function to implement primitives like +
-type RustClosureFn = fn(Vec<Arc<Type>>) -> Arc<Type>;
+// A closure function to implement primitives like +, which is given the global environment so
+// that it can call back into functions it is passed
+pub type RustClosureFn =
+  fn(Vec<Arc<Type>>, &mut GlobalEnv) -> Result<Arc<Type>, RuntimeError>;
 
 #[derive(Debug, Clone)]
 pub enum Type {
   Unit,
   Free(Arc<Expr>),
-  Number(f32),
+  Number(Number),
   Str(String),
+  Char(char),
+  Symbol(String),
   Bool(bool),
   Tuple(Arc<Type>, Arc<Type>),
+  // An error's message along with any data describing it
+  Error(String, Arc<Type>),
 
   Closure(Arc<Option<Env>>, Arc<Defn>),
 
   List(Arc<List>),
 
-  RustClosure(Arc<RustClosureFn>),
+  // Persistent hash maps and sets, where the values of a set's map are all unit
+  Map(Map),
+  Set(Map),
+
+  // A primitive with the number of arguments it accepts, which is checked before it is called
+  RustClosure(Arc<RustClosureFn>, Arity),
+
+  // A closure compiled for the bytecode machine
+  VmClosure(Arc<vm::machine::Closure>),
+
+  // A mutable reference to a value
+  Box(Arc<RwLock<Arc<Type>>>),
 }
 
 impl Type {
   pub fn new_empty_list() -> Arc<Type> {
     Arc::new(Type::List(Arc::new(List::End)))
   }
-  pub fn new_number(n: f32) -> Arc<Type> {
-    Arc::new(Type::Number(n))
+  pub fn new_number<N: Into<Number>>(n: N) -> Arc<Type> {
+    Arc::new(Type::Number(n.into()))
   }
   pub fn unit() -> Arc<Type> {
     Arc::new(Type::Unit)
   }
+  // A primitive which accepts any number of arguments
   pub fn new_rust_closure(r: RustClosureFn) -> Arc<Type> {
-    Arc::new(Type::RustClosure(Arc::new(r)))
+    Type::new_builtin(Arity::AtLeast(0), r)
+  }
+  pub fn new_builtin(arity: Arity, r: RustClosureFn) -> Arc<Type> {
+    Arc::new(Type::RustClosure(Arc::new(r), arity))
+  }
+  pub fn type_name(&self) -> &'static str {
+    match self {
+      Type::Unit => "unit",
+      Type::Free(_) => "free",
+      Type::Number(_) => "number",
+      Type::Str(_) => "string",
+      Type::Char(_) => "char",
+      Type::Symbol(_) => "symbol",
+      Type::Bool(_) => "bool",
+      Type::Tuple(_, _) => "tuple",
+      Type::Error(_, _) => "error",
+      Type::Closure(_, _) | Type::RustClosure(..) | Type::VmClosure(_) => "function",
+      Type::List(_) => "list",
+      Type::Map(_) => "map",
+      Type::Set(_) => "set",
+      Type::Box(_) => "box",
+    }
   }
   pub fn cons(a: &Arc<Type>, b: &Arc<List>) -> Arc<List> {
     Arc::new(List::Cons(Arc::clone(a), Arc::clone(b)))
   }
+  pub fn new_list(items: Vec<Arc<Type>>) -> Arc<Type> {
+    Arc::new(Type::List(items.iter().rev()
+      .fold(Arc::new(List::End), |l, next| Type::cons(next, &l))))
+  }
 }
 
 #[derive(Debug, Clone)]
@@ -54,123 +101,344 @@ pub enum ParamType {
 
 #[derive(Debug, Clone)]
 pub struct Defn {
+  // empty for an anonymous function
   pub name: String,
   pub params: Vec<ParamType>,
   pub body: Arc<Expr>,
 }
 
+impl Defn {
+  pub fn arity(&self) -> Arity {
+    let singular = self.params.iter().filter(|p| match p {
+      ParamType::Singular(_) => true,
+      ParamType::Rest(_) => false,
+    }).count();
+    if singular == self.params.len() { Arity::Exactly(singular) } else { Arity::AtLeast(singular) }
+  }
+  // Creates the environment the body is evaluated in, with the function bound to its own name
+  // so that it can recurse.
+  fn bind(&self, func: &Arc<Type>, clos_env: &Arc<Option<Env>>, args: Vec<Arc<Type>>)
+    -> Result<Arc<Option<Env>>, RuntimeError> {
+    self.arity().check(args.len())?;
+    let mut slots = Vec::with_capacity(self.params.len() + 1);
+    slots.push(Arc::clone(func));
+    let mut args = args.into_iter();
+    for p in self.params.iter() {
+      slots.push(match p {
+        ParamType::Singular(_) => args.next().unwrap(),
+        ParamType::Rest(_) => Type::new_list(args.by_ref().collect()),
+      });
+    }
+    Ok(Env::with(Arc::clone(clos_env), slots))
+  }
+}
+
+// Evaluates body, and if it fails binds the raised value to the catch's name and evaluates its
+// handler. Finally is evaluated afterwards no matter how the others turned out.
+#[derive(Debug, Clone)]
+pub struct Try {
+  pub body: Arc<Expr>,
+  pub catch: Option<(String, Arc<Expr>)>,
+  pub finally: Option<Arc<Expr>>,
+}
+
 #[derive(Debug, Clone)]
 pub enum Assign {
   Local(String, Arc<Expr>, Arc<Expr>),
   Global(String, Arc<Expr>),
+  // Binds every name in one frame, with the values evaluated outside of it
+  Locals(Vec<(String, Arc<Expr>)>, Arc<Expr>),
+  // Binds every name in one frame, with the values evaluated in order inside of it so that they
+  // can refer to each other. A name is unit until its value has been evaluated.
+  Rec(Vec<(String, Arc<Expr>)>, Arc<Expr>),
+  // Changes the value of an existing variable, which is a Variable until it has been resolved
+  Set(Arc<Expr>, Arc<Expr>),
 }
 
 #[derive(Debug, Clone)]
 pub enum Expr {
   Value(Arc<Type>),
   Variable(String),
+  // A variable which has been resolved to the frame it is in and its slot in that frame
+  Local(usize, usize),
+  // A variable which has been resolved to a global's slot
+  Global(usize),
   Defn(Arc<Defn>),
   Call(Arc<Expr>, Vec<Arc<Expr>>),
   Assign(Assign),
   If(Arc<Expr>, Arc<Expr>, Arc<Expr>),
+  Try(Arc<Try>),
+  // Evaluates each expression in order, giving back the value of the last
+  Seq(Vec<Arc<Expr>>),
 }
 
-#[derive(Debug, Clone)]
+// A frame of local variables, laid out as described in resolve.rs. The slots can be written to
+// after the frame is made, which is how letrec ties its knot.
+#[derive(Debug)]
 pub struct Env {
-  name: String,
-  bind: Arc<Expr>,
+  slots: RwLock<Vec<Arc<Type>>>,
   old: Arc<Option<Env>>,
 }
 
 impl Env {
-  pub fn with(old: Arc<Option<Env>>, name: String, bind: Arc<Expr>) -> Arc<Option<Env>> {
-    Arc::new(Some(Env{name, bind, old}))
+  pub fn with(old: Arc<Option<Env>>, slots: Vec<Arc<Type>>) -> Arc<Option<Env>> {
+    Arc::new(Some(Env{slots: RwLock::new(slots), old}))
   }
-  fn lookup(env: Arc<Option<Env>>, name: String) -> Option<Arc<Expr>> {
-    let e = if let Some(v) = env.borrow() { v } else { return None };
-    if e.name == name {
-      return Some(Arc::clone(&e.bind))
+  fn frame(mut env: &Arc<Option<Env>>, depth: usize) -> &Env {
+    for _ in 0..depth {
+      env = &Option::as_ref(env).expect("Resolved past the outermost frame").old;
     }
-    Env::lookup(Arc::clone(&e.old), name)
+    Option::as_ref(env).expect("Resolved past the outermost frame")
+  }
+  fn lookup(env: &Arc<Option<Env>>, depth: usize, i: usize) -> Arc<Type> {
+    Arc::clone(&Env::frame(env, depth).slots.read().unwrap()[i])
+  }
+  fn set(env: &Arc<Option<Env>>, depth: usize, i: usize, v: Arc<Type>) {
+    Env::frame(env, depth).slots.write().unwrap()[i] = v;
   }
 }
 
-pub type GlobalEnv = HashMap<String, Arc<Expr>>;
+// Global variables, which are resolved to an index into their values. A global can have a slot
+// before it is assigned to, so that it can be referred to ahead of time.
+#[derive(Debug, Clone)]
+pub struct GlobalEnv {
+  indices: HashMap<String, usize>,
+  values: Vec<(String, Option<Arc<Type>>)>,
+  // functions from the unevaluated operands of a form to the code it expands to
+  macros: HashMap<String, Arc<Type>>,
+}
+
+impl GlobalEnv {
+  pub fn new() -> Self {
+    GlobalEnv{indices: HashMap::new(), values: Vec::new(), macros: HashMap::new()}
+  }
+  pub fn index_of(&self, name: &str) -> Option<usize> {
+    self.indices.get(name).cloned()
+  }
+  // Returns the slot for the name, creating one if it does not exist
+  pub fn declare(&mut self, name: &str) -> usize {
+    if let Some(i) = self.index_of(name) {
+      return i
+    }
+    self.values.push((name.to_string(), None));
+    self.indices.insert(name.to_string(), self.values.len() - 1);
+    self.values.len() - 1
+  }
+  pub fn define(&mut self, name: &str, v: Arc<Type>) -> usize {
+    let i = self.declare(name);
+    self.values[i].1 = Some(v);
+    i
+  }
+  pub fn get(&self, name: &str) -> Option<Arc<Type>> {
+    self.index_of(name).and_then(|i| self.values[i].1.clone())
+  }
+  pub fn define_macro(&mut self, name: &str, expander: Arc<Type>) {
+    self.macros.insert(name.to_string(), expander);
+  }
+  pub fn get_macro(&self, name: &str) -> Option<Arc<Type>> {
+    self.macros.get(name).cloned()
+  }
+  pub fn get_slot(&self, i: usize) -> Result<Arc<Type>, RuntimeError> {
+    match &self.values[i] {
+      (_, Some(v)) => Ok(Arc::clone(v)),
+      (name, None) => Err(RuntimeError::UnboundVariable(name.to_string())),
+    }
+  }
+  // Changes the value of a global, which must already have one
+  pub fn set_slot(&mut self, i: usize, v: Arc<Type>) -> Result<(), RuntimeError> {
+    match &mut self.values[i] {
+      (_, Some(old)) => {
+        *old = v;
+        Ok(())
+      },
+      (name, None) => Err(RuntimeError::UnboundVariable(name.to_string())),
+    }
+  }
+}
 
 impl Expr {
-  fn to_type(&self) -> Arc<Type> {
+  pub fn to_type(&self) -> Arc<Type> {
     match self {
       Expr::Value(v) => Arc::clone(v),
       _ => panic!("Not a type"),
     }
   }
-  pub fn eval(&self, env: Arc<Option<Env>>, g_env: &mut GlobalEnv) -> Arc<Expr> {
-    match self {
-      Expr::Value(v) => Arc::new(Expr::Value(Arc::clone(v))),
-      Expr::Variable(name) => match Env::lookup(env, name.to_string()) {
-        Some(expr) => expr,
-        None => match g_env.get(name) {
-          Some(expr) => Arc::clone(expr),
-          None => panic!("Free variable {}", name),
-        },
+  // Calls a function with arguments which have already been evaluated, which is how builtins
+  // call back into closures from the program
+  pub fn apply(func: &Arc<Type>, args: Vec<Arc<Type>>, g_env: &mut GlobalEnv)
+    -> Result<Arc<Type>, RuntimeError> {
+    let args = args.into_iter().map(|a| Arc::new(Expr::Value(a))).collect();
+    Expr::Call(Arc::new(Expr::Value(Arc::clone(func))), args).eval(Arc::new(None), g_env)
+      .map(|v| v.to_type())
+  }
+  pub fn eval(&self, env: Arc<Option<Env>>, g_env: &mut GlobalEnv)
+    -> Result<Arc<Expr>, RuntimeError> {
+    let mut step = self.step(env, g_env)?;
+    loop {
+      step = match step {
+        Step::Done(v) => return Ok(v),
+        Step::Tail(expr, env) => expr.step(env, g_env)?,
       }
+    }
+  }
+  // Evaluates everything except for an expression in tail position, which is handed back to
+  // eval so that tail calls do not grow the stack.
+  fn step(&self, env: Arc<Option<Env>>, g_env: &mut GlobalEnv) -> Result<Step, RuntimeError> {
+    Ok(Step::Done(match self {
+      Expr::Value(v) => Arc::new(Expr::Value(Arc::clone(v))),
+      // only globals are left unresolved
+      Expr::Variable(name) => match g_env.get(name) {
+        Some(v) => Arc::new(Expr::Value(v)),
+        None => return Err(RuntimeError::UnboundVariable(name.to_string())),
+      },
+      Expr::Local(depth, i) => Arc::new(Expr::Value(Env::lookup(&env, *depth, *i))),
+      Expr::Global(i) => Arc::new(Expr::Value(g_env.get_slot(*i)?)),
       Expr::Assign(a) => match a {
-        Assign::Local(name, val, body) => {
-          let evald = val.eval(Arc::clone(&env), g_env);
-          body.eval(Env::with(env, name.to_string(), evald), g_env)
+        Assign::Local(_, val, body) => {
+          let evald = val.eval(Arc::clone(&env), g_env)?.to_type();
+          return Ok(Step::Tail(Arc::clone(body), Env::with(env, vec!(evald))))
         },
         Assign::Global(name, val) => {
-          let evald = val.eval(Arc::clone(&env), g_env);
-          g_env.insert(name.to_string(), evald);
+          let evald = val.eval(Arc::clone(&env), g_env)?.to_type();
+          g_env.define(name, evald);
           Arc::new(Expr::Value(Type::unit()))
         },
-      },
-      Expr::Defn(defn) => Arc::new(Expr::Value(Arc::new(Type::Closure(env, Arc::clone(defn))))),
-      Expr::If(cond, pred, fallback) => match cond.eval(Arc::clone(&env), g_env).deref() {
-        Expr::Value(inner) => match inner.borrow() {
-          Type::Bool(true) => pred.eval(env, g_env),
-          _ => fallback.eval(env, g_env),
+        Assign::Locals(bindings, body) => {
+          let evald = bindings.iter()
+            .map(|(_, val)| val.eval(Arc::clone(&env), g_env).map(|v| v.to_type()))
+            .collect::<Result<Vec<_>, _>>()?;
+          return Ok(Step::Tail(Arc::clone(body), Env::with(env, evald)))
+        },
+        Assign::Set(target, val) => {
+          let evald = val.eval(Arc::clone(&env), g_env)?.to_type();
+          match target.borrow() {
+            Expr::Local(depth, i) => Env::set(&env, *depth, *i, evald),
+            Expr::Global(i) => g_env.set_slot(*i, evald)?,
+            Expr::Variable(name) => match g_env.index_of(name) {
+              Some(i) => g_env.set_slot(i, evald)?,
+              None => return Err(RuntimeError::UnboundVariable(name.to_string())),
+            },
+            target => panic!("Cannot assign to {:?}", target),
+          };
+          Arc::new(Expr::Value(Type::unit()))
+        },
+        Assign::Rec(bindings, body) => {
+          let env = Env::with(env, bindings.iter().map(|_| Type::unit()).collect());
+          for (i, (_, val)) in bindings.iter().enumerate() {
+            let evald = val.eval(Arc::clone(&env), g_env)?.to_type();
+            Env::set(&env, 0, i, evald);
+          }
+          return Ok(Step::Tail(Arc::clone(body), env))
         },
-        _ => fallback.eval(env, g_env),
       },
-      Expr::Call(operator, operands) => match operator.eval(Arc::clone(&env), g_env).deref() {
-        Expr::Value(inner) => match inner.borrow() {
-          Type::Closure(clos_env, defn) => {
-            let fn_env = Env::with(Arc::clone(clos_env), defn.name.to_string(),
-              Arc::clone(&defn.body));
-            let fn_env = {
-              let args = &mut operands.iter().map(|n| n.eval(Arc::clone(&env), g_env));
-              defn.params.iter().fold(fn_env, move |e,p| match p {
-                ParamType::Singular(name) =>
-                  Env::with(e, name.to_string(), Arc::clone(&args.next()
-                    .expect("Not enough args passed to function"))),
-
-                ParamType::Rest(name) => Env::with(e, name.to_string(),
-                  Arc::new(Expr::Value(Arc::new(Type::List(args.fold(Arc::new(List::End), |r,n|
-                    Arc::new(List::Cons(n.to_type(),Arc::clone(&r))))))))),
-              })
-            };
-
-            defn.body.eval(fn_env, g_env)
+      Expr::Defn(defn) => Arc::new(Expr::Value(Arc::new(Type::Closure(env, Arc::clone(defn))))),
+      Expr::If(cond, pred, fallback) =>
+        return Ok(match cond.eval(Arc::clone(&env), g_env)?.to_type().borrow() {
+          Type::Bool(true) => Step::Tail(Arc::clone(pred), env),
+          _ => Step::Tail(Arc::clone(fallback), env),
+        }),
+      Expr::Call(operator, operands) => {
+        let func = operator.eval(Arc::clone(&env), g_env)?.to_type();
+        let args = operands.iter()
+          .map(|it| it.eval(Arc::clone(&env), g_env).map(|v| v.to_type()))
+          .collect::<Result<Vec<_>, _>>()?;
+        match func.borrow() {
+          Type::Closure(clos_env, defn) =>
+            return Ok(Step::Tail(Arc::clone(&defn.body), defn.bind(&func, clos_env, args)?)),
+          Type::RustClosure(f, arity) => {
+            arity.check(args.len())?;
+            Arc::new(Expr::Value(f(args, g_env)?))
           },
-          Type::RustClosure(func) => Arc::new(Expr::Value(func(operands.iter()
-            .map(|it| it.eval(Arc::clone(&env), g_env).to_type()).collect()))),
-          _ => panic!("Cannot invoke non-function")
+          Type::VmClosure(c) => Arc::new(Expr::Value(vm::machine::call(c, &func, args, g_env)?)),
+          _ => return Err(RuntimeError::NotCallable(Arc::clone(&func))),
+        }
+      },
+      Expr::Seq(exprs) => match exprs.split_last() {
+        Some((last, init)) => {
+          for e in init {
+            e.eval(Arc::clone(&env), g_env)?;
+          }
+          return Ok(Step::Tail(Arc::clone(last), env))
         },
-        _ => panic!("Cannot invoke non-function"),
+        None => Arc::new(Expr::Value(Type::unit())),
       },
-    }
+      Expr::Try(t) => {
+        let result = match (t.body.eval(Arc::clone(&env), g_env), &t.catch) {
+          (Err(e), Some((_, handler))) =>
+            handler.eval(Env::with(Arc::clone(&env), vec!(e.to_value())), g_env),
+          (result, _) => result,
+        };
+        match &t.finally {
+          Some(finally) => finally.eval(env, g_env).and(result)?,
+          None => result?,
+        }
+      },
+    }))
   }
 }
 
+// What is left to do after a step of evaluation, either nothing or evaluating an expression in
+// tail position.
+enum Step {
+  Done(Arc<Expr>),
+  Tail(Arc<Expr>, Arc<Option<Env>>),
+}
+
 #[test]
 fn test_basic() {
-  let test_num = 3.0;
+  let test_num = Number::Int(3);
   let expr = Expr::Assign(Assign::Local(String::from("x"),
     Arc::new(Expr::Value(Arc::new(Type::Number(test_num)))),
     Arc::new(Expr::Variable(String::from("x")))));
-  let out = expr.eval(Arc::new(None), &mut HashMap::new());
+  let mut g_env = GlobalEnv::new();
+  let out = expr.resolve(&mut g_env).unwrap().eval(Arc::new(None), &mut g_env).unwrap();
   println!("{:?}", out);
 }
 
 
+
+#[test]
+fn test_runtime_errors() {
+  let call = |f: &str, args: Vec<f64>| Expr::Call(Arc::new(Expr::Variable(String::from(f))),
+    args.into_iter().map(|n| Arc::new(Expr::Value(Type::new_number(n)))).collect());
+  let mut g_env = Env::default_global();
+
+  match Expr::Variable(String::from("nope")).eval(Env::default(), &mut g_env) {
+    Err(RuntimeError::UnboundVariable(ref name)) if name == "nope" => (),
+    other => panic!("Expected unbound variable, got {:?}", other),
+  }
+  match call("hd", vec!(1.0)).eval(Env::default(), &mut g_env) {
+    Err(ref e @ RuntimeError::TypeMismatch{expected: "list", ..}) =>
+      assert_eq!(e.to_string(), "Expected list, got number 1.0"),
+    other => panic!("Expected type mismatch, got {:?}", other),
+  }
+  match Expr::Call(Arc::new(Expr::Value(Type::new_number(1.0))), vec!())
+    .eval(Env::default(), &mut g_env) {
+    Err(ref e @ RuntimeError::NotCallable(_)) =>
+      assert_eq!(e.to_string(), "Cannot invoke non-function 1.0"),
+    other => panic!("Expected not callable, got {:?}", other),
+  }
+
+  let defn = Defn{name: String::from("f"), params: vec!(ParamType::Singular(String::from("x"))),
+    body: Arc::new(Expr::Variable(String::from("x")))};
+  let f = Expr::Defn(Arc::new(defn)).resolve(&mut g_env).unwrap()
+    .eval(Env::default(), &mut g_env).unwrap().to_type();
+  g_env.define("f", f);
+  match call("f", vec!(1.0, 2.0)).eval(Env::default(), &mut g_env) {
+    Err(RuntimeError::Arity{expected: Arity::Exactly(1), got: 2}) => (),
+    other => panic!("Expected arity mismatch, got {:?}", other),
+  }
+}
+
+#[test]
+fn test_tail_calls() {
+  use lisp_parse::parse;
+
+  let mut g_env = Env::default_global();
+  let src = "(let count (defn count n acc (if (= n 0) acc (let m (- n 1) (count m (+ acc 1))))))";
+  let mut eval = |src: &str| parse(String::from(src)).unwrap()[0].to_ast().unwrap()
+    .resolve(&mut g_env).unwrap().eval(Env::default(), &mut g_env).unwrap();
+  eval(src);
+  let out = eval("(count 100000 0)");
+  assert!(out.to_type().equals(&Type::new_number(Number::Int(100000))));
+}
@@ -2,6 +2,7 @@ extern crate proof;
 
 use std::env;
 use std::io;
+use std::process;
 use std::fs::File;
 use std::io::prelude::*;
 use proof::compile::compile::compile;
@@ -9,10 +10,13 @@ use proof::compile::compile::compile;
 fn main() {
   let mut out = io::stdout();
   env::args().skip(1).for_each(|arg| {
-    let mut file = File::open(arg).expect("File could not be opened");
+    let mut file = File::open(&arg).expect("File could not be opened");
     let mut contents = String::new();
     file.read_to_string(&mut contents).expect("File could not be read");
-    let parts = proof::lisp_parse::parse(contents);
+    let parts = proof::lisp_parse::parse(contents).unwrap_or_else(|e| {
+      eprintln!("{}: {}", arg, e);
+      process::exit(1);
+    });
     compile(parts.iter().next().unwrap(), &mut out).expect("Could not compile");
   });
 }
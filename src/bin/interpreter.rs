@@ -1,25 +1,44 @@
 extern crate proof;
+use std::env;
 use std::io::Write;
 use std::io::{self, stdout};
+use proof::lisp_parse::{balance, Balance};
+use proof::ast::Env;
+use proof::{expand, resolve};
 
 fn main() {
   // tokenize input str and then turn into ast
   // then evaluate
 //  println!("{:?}", proof::lisp_parse::parse(buffer).to_ast().eval(proof::ast::Env::default()));
+  // --vm runs input on the bytecode machine rather than walking the tree
+  let use_vm = env::args().skip(1).any(|arg| arg == "--vm");
   let mut buffer = String::new();
-  let mut global_env = proof::ast::Env::default_global();
+  let mut global_env = Env::default_global();
   loop {
     print!(">> ");
     stdout().flush().expect("Could not flush to stdout, strange.");
     match io::stdin().read_line(&mut buffer) {
       Ok(0) => break,
       Ok(_) => if has_matching_parens(&buffer) {
-        proof::lisp_parse::parse(buffer)
-          .iter()
-          .for_each(|tokenized| {
-            print!("= {:?}",
-              tokenized.to_ast().eval(proof::ast::Env::default(), &mut global_env));
-          });
+        match proof::lisp_parse::parse(buffer) {
+          Ok(tokens) => match tokens.iter()
+            .map(|t| expand::expand(t, &mut global_env).and_then(|t| t.to_ast()))
+            .collect::<Result<Vec<_>, _>>() {
+            Ok(asts) => match resolve::program(&asts, &mut global_env) {
+              Ok(resolved) => resolved.iter().for_each(|ast| match if use_vm {
+                proof::vm::machine::eval(ast, &mut global_env)
+              } else {
+                ast.eval(Env::default(), &mut global_env).map(|v| v.to_type())
+              } {
+                Ok(result) => print!("= {}", result),
+                Err(e) => print!("Error: {}", e),
+              }),
+              Err(e) => print!("Error: {}", e),
+            },
+            Err(e) => print!("Syntax error: {}", e),
+          },
+          Err(e) => print!("Parse error: {}", e),
+        }
         buffer = String::new();
       },
       Err(e) => {
@@ -32,25 +51,13 @@ fn main() {
   println!("\nFac ut vivas!");
 }
 
-// this was my google internship question today lol
 fn has_matching_parens(s: &String) -> bool {
-  let mut count = 0;
-  for c in s.chars() {
-    match c {
-      '(' => count = count + 1,
-      ')' => {
-        count = count - 1;
-        if count < 0 {
-          return false
-        }
-      }
-      _ => (),
-    }
-  }
-  if count == 0 {
-    true
-  } else {
-    println!("        ----------------- Missing {} ) parens", count);
-    false
+  match balance(s) {
+    Balance::Complete => true,
+    Balance::Open(count) => {
+      println!("        ----------------- Missing {} ) parens", count);
+      false
+    },
+    Balance::Unterminated => false,
   }
 }
@@ -1,7 +1,7 @@
 use std::io::prelude::*;
 use std::io;
 
-use lisp_parse::Token;
+use lisp_parse::{Token, read_char};
 
 use std::collections::HashMap;
 use compile::labels::{Counter};
@@ -75,37 +75,97 @@ enum Sexp {
   Immed(Immed),
   Expr(String, Vec<Sexp>),
   If(Box<Sexp>, Box<Sexp>, Box<Sexp>),
+  // The key, which is evaluated once and kept on the stack while it is compared against the
+  // datums of each clause, the clauses, and what to do if none of them match
+  Case(Box<Sexp>, Vec<(Vec<Immed>, Sexp)>, Box<Sexp>),
   Malformed(String),
 }
 
 impl Sexp {
   fn type_of(t: &Token) -> Self {
     match t {
-      Token::Word(s) => match s.as_ref() {
+      Token::Word(s, _) => match s.as_ref() {
         "#t" => Sexp::Immed(Immed::Bool(true)),
         "#f" => Sexp::Immed(Immed::Bool(false)),
         "nil" => Sexp::Immed(Immed::Nil),
         "()" => Sexp::Immed(Immed::Nil),
         _ if s.trim().parse::<i32>().is_ok() =>
           Sexp::Immed(Immed::Fixnum(s.trim().parse::<i32>().unwrap())),
-        _ if s.len() == 3 && s.starts_with("#\\") =>
-          Sexp::Immed(Immed::Char(s.bytes().last().unwrap())),
+        _ if read_char(s).map_or(false, |c| c.is_ascii()) =>
+          Sexp::Immed(Immed::Char(read_char(s).unwrap() as u8)),
         _ => Sexp::Malformed(s.to_string()),
       },
-      Token::Group(g) => match g.as_slice() {
+      Token::Str(s, _) => Sexp::Malformed(s.to_string()),
+      Token::Braced(brace, ..) => Sexp::Malformed(brace.open().to_string()),
+      Token::Group(g, _) => match g.as_slice() {
         [] => Sexp::Immed(Immed::Nil),
-        [Token::Word(if_string), cond, pred, alt] if if_string == "if" =>
+        [Token::Word(if_string, _), cond, pred, alt] if if_string == "if" =>
           Sexp::If(
             Box::new(Sexp::type_of(cond)),
             Box::new(Sexp::type_of(pred)),
             Box::new(Sexp::type_of(alt)),
           ),
-        [Token::Word(fn_name), args..] =>
+        [Token::Word(form, _), args..] if is_derived(form) => Sexp::derived(form, args),
+        [Token::Word(fn_name, _), args..] =>
           Sexp::Expr(fn_name.to_string(), args.iter().map(|arg| Sexp::type_of(arg)).collect()),
         _ => unimplemented!(), // This is the case where the first arg evals to fn
       },
     }
   }
+  // Lowers the forms which are sugar for nested ifs, along with case
+  fn derived(form: &str, args: &[Token]) -> Self {
+    let sexp = |t: &Token| Box::new(Sexp::type_of(t));
+    let immed = |v: Immed| Box::new(Sexp::Immed(v));
+    match (form, args) {
+      ("when", [cond, body]) => Sexp::If(sexp(cond), sexp(body), immed(Immed::Nil)),
+      ("unless", [cond, body]) => Sexp::If(sexp(cond), immed(Immed::Nil), sexp(body)),
+      ("and", []) => Sexp::Immed(Immed::Bool(true)),
+      ("and", [first, rest..]) => Sexp::If(sexp(first), Box::new(Sexp::derived("and", rest)),
+        immed(Immed::Bool(false))),
+      ("or", []) => Sexp::Immed(Immed::Bool(false)),
+      ("or", [first, rest..]) => Sexp::If(sexp(first), immed(Immed::Bool(true)),
+        Box::new(Sexp::derived("or", rest))),
+      ("cond", []) => Sexp::Immed(Immed::Nil),
+      ("cond", [Token::Group(clause, _), rest..]) => match clause.as_slice() {
+        [Token::Word(else_string, _), body] if else_string == "else" && rest.is_empty() =>
+          Sexp::type_of(body),
+        [cond, body] => Sexp::If(sexp(cond), sexp(body), Box::new(Sexp::derived("cond", rest))),
+        _ => Sexp::Malformed(String::from("cond")),
+      },
+      ("case", [key, clauses..]) => {
+        let mut arms = Vec::new();
+        let mut fallback = Sexp::Immed(Immed::Nil);
+        for (i, clause) in clauses.iter().enumerate() {
+          let clause = match clause {
+            Token::Group(clause, _) => clause,
+            _ => return Sexp::Malformed(String::from("case")),
+          };
+          match clause.as_slice() {
+            [Token::Word(else_string, _), body]
+              if else_string == "else" && i == clauses.len() - 1 =>
+              fallback = Sexp::type_of(body),
+            [datums, body] => {
+              let datums = match datums {
+                Token::Group(ds, _) => ds.iter().collect(),
+                d => vec!(d),
+              };
+              let immeds = datums.into_iter().map(|d| match Sexp::type_of(d) {
+                Sexp::Immed(v) => Some(v),
+                _ => None,
+              }).collect::<Option<Vec<_>>>();
+              match immeds {
+                Some(immeds) => arms.push((immeds, Sexp::type_of(body))),
+                None => return Sexp::Malformed(String::from("case")),
+              }
+            },
+            _ => return Sexp::Malformed(String::from("case")),
+          }
+        }
+        Sexp::Case(sexp(key), arms, Box::new(fallback))
+      },
+      _ => Sexp::Malformed(form.to_string()),
+    }
+  }
   fn emit(&self, w: &mut Write) -> io::Result<()> {
     match self {
       Sexp::Immed(v) => write!(w, "mov ${:#b}, %eax\n", v.value()),
@@ -139,12 +199,36 @@ impl Sexp {
         alt.emit(w)?;
         write!(w, "end_if_{}:\n", label)
       },
+      Sexp::Case(key, arms, fallback) => {
+        let label = unique_label.lock().unwrap().take();
+
+        key.emit(w)?;
+        write!(w, "push %rax\n")?;
+        for (i, (datums, _)) in arms.iter().enumerate() {
+          for d in datums {
+            write!(w, "cmpl ${}, (%rsp)\n        je case_{}_{}\n", d.value(), label, i)?;
+          }
+        }
+        write!(w, "add $8, %rsp\n")?;
+        fallback.emit(w)?;
+        write!(w, "jmp end_case_{}\n", label)?;
+        for (i, (_, body)) in arms.iter().enumerate() {
+          write!(w, "case_{}_{}:\n        add $8, %rsp\n", label, i)?;
+          body.emit(w)?;
+          write!(w, "jmp end_case_{}\n", label)?;
+        }
+        write!(w, "end_case_{}:\n", label)
+      },
       Sexp::Malformed(_) => panic!("Emit called on malformed"),
     }
   }
 }
 
 
+fn is_derived(form: &str) -> bool {
+  ["when", "unless", "and", "or", "cond", "case"].contains(&form)
+}
+
 enum Immed {
   Fixnum(i32),
   Bool(bool),
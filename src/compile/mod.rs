@@ -64,6 +64,24 @@ mod tests {
     )
   }
 
+  fn derived_test_cases() -> Vec<(&'static str, &'static str)> {
+    vec!(
+      ("(when #t 1)", "1"),
+      ("(unless #t 1)", "nil"),
+      ("(and #t #t)", "#t"),
+      ("(and #t #f)", "#f"),
+      ("(or #f #t)", "#t"),
+      ("(or)", "#f"),
+      ("(cond ((fxzero? 1) 1) ((fxzero? 0) 2) (else 3))", "2"),
+      ("(cond (#f 1) (else 3))", "3"),
+      ("(case (fx+ 1 1) ((1 3) 1) ((2 4) 2) (else 3))", "2"),
+      ("(case 5 ((1 3) 1) (else 3))", "3"),
+      ("(case (fxadd1 #\\a) ((1 2) 1) (else 3))", "3"),
+      ("(case #\\b ((#\\a) 1) ((#\\c #\\b) (fxadd1 1)))", "2"),
+      ("(fx+ (case 1 (1 10)) (case 2 (1 10) (else 5)))", "15"),
+    )
+  }
+
   fn two_arg_test_cases() -> Vec<(&'static str, &'static str)> {
     vec!(
       ("(fx+ 1 2)", "3"),
@@ -101,7 +119,7 @@ mod tests {
     let errors: Vec<String> = cases.into_iter().enumerate().filter_map(|(i, (input, expected))| {
       let filename = format!("tmp{}_{}.s", name, i);
       let mut file = File::create(&filename).expect("Cannot open temp file");
-      compile::compile(&first(parse(String::from(input))), &mut file)
+      compile::compile(&first(parse(String::from(input)).expect("Could not parse")), &mut file)
         .expect("Could not compile");
       let newfile = format!("exe_{}_{}", name, i);
       let comp_out = Command::new("gcc")
@@ -143,6 +161,7 @@ mod tests {
     run_on(one_arg_test_cases(), "one_arg");
     run_on(if_test_cases(), "if");
     run_on(two_arg_test_cases(), "two_arg");
+    run_on(derived_test_cases(), "derived");
     // run_on(...)
   }
 }
@@ -1,96 +1,283 @@
-use ast::{Env, Type, Expr, GlobalEnv, List};
-use std::sync::Arc;
+use ast::{Env, Type, GlobalEnv, List};
+use error::{Arity, RuntimeError};
+use number::Number;
+use math;
+use list;
+use string;
+use map;
+use std::sync::{Arc, RwLock};
 use std::borrow::Borrow;
-use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+static GENSYM_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+fn list_of(v: &Arc<Type>) -> Result<&Arc<List>, RuntimeError> {
+  match v.borrow() {
+    Type::List(l) => Ok(l),
+    _ => Err(RuntimeError::mismatch("list", v)),
+  }
+}
+
+// Defines a builtin which gives back whether its one argument matches the pattern
+macro_rules! predicate {
+  ($e: expr, $name: expr, $($p: pat)|+ $(if $guard: expr)?) => {
+    $e.define($name, Type::new_builtin(Arity::Exactly(1), |x, _|
+      Ok(Arc::new(Type::Bool(match x[0].borrow() { $($p)|+ $(if $guard)? => true, _ => false })))));
+  };
+}
+
+// The arity of a function as a value, which is the number of arguments if it is fixed, and
+// otherwise (at-least min) or (between min max)
+fn arity_value(arity: Arity) -> Arc<Type> {
+  let symbol = |s: &str| Arc::new(Type::Symbol(s.to_string()));
+  let number = |n: usize| Type::new_number(n as i64);
+  match arity {
+    Arity::Exactly(n) => number(n),
+    Arity::AtLeast(min) => Type::new_list(vec!(symbol("at-least"), number(min))),
+    Arity::Between(min, max) => Type::new_list(vec!(symbol("between"), number(min), number(max))),
+  }
+}
 
 impl Env {
   pub fn default() -> Arc<Option<Env>> {
-    let e = Arc::new(None);
-    let e = Env::with(e, String::from("+"), Arc::new(Expr::Value(
-      Type::new_rust_closure(|x: Vec<Arc<Type>>|
-        Type::new_number(x.iter().fold(0.0, |acc, elem| match elem.borrow() {
-      Type::Number(n) => acc + n,
-      _ => panic!("Cannot add non-number"),
-    }))))));
-
-    let e = Env::with(e, String::from("cons"), Arc::new(Expr::Value(
-      Type::new_rust_closure(|x: Vec<Arc<Type>>|{
+    Arc::new(None)
+  }
+  pub fn default_global() -> GlobalEnv {
+    let mut e = GlobalEnv::new();
+    math::define(&mut e);
+    list::define(&mut e);
+    string::define(&mut e);
+    map::define(&mut e);
+
+    e.define("cons", Type::new_builtin(Arity::AtLeast(1), |x: Vec<Arc<Type>>, _| {
       let mut items = x.iter().rev();
-      let first = items.next().expect("Missing arguments, usage: cons [...items] [into list]");
-      if let Type::List(sub) = first.borrow() {
-        Arc::new(Type::List(items.fold(Arc::clone(sub), |l, next| Type::cons(next, &l))))
-      } else {
-        panic!("Last element was expected to be array");
+      let sub = list_of(items.next().unwrap())?;
+      Ok(Arc::new(Type::List(items.fold(Arc::clone(sub), |l, next| Type::cons(next, &l)))))
+    }));
+
+    e.define("debug", Type::new_rust_closure(|x: Vec<Arc<Type>>, _| {
+      x.iter().for_each(|item| println!("?:{}", item));
+      Ok(Type::unit())
+    }));
+
+    e.define("=", Type::new_builtin(Arity::AtLeast(1), equal));
+
+    e.define("hd", Type::new_builtin(Arity::Exactly(1), hd));
+    e.define("tl", Type::new_builtin(Arity::Exactly(1), tl));
+
+    e.define("pair", Type::new_builtin(Arity::Exactly(2), pair));
+    e.define("tuple", Type::new_builtin(Arity::Exactly(2), pair));
+    e.define("fst", Type::new_builtin(Arity::Exactly(1), fst));
+    e.define("snd", Type::new_builtin(Arity::Exactly(1), snd));
+
+    e.define("error",
+      Type::new_builtin(Arity::AtLeast(1), |x, _| {
+        let payload = if x.len() > 1 { Type::new_list(x[1..].to_vec()) } else { Type::unit() };
+        match x[0].borrow() {
+          Type::Str(message) => Ok(Arc::new(Type::Error(message.to_string(), payload))),
+          _ => Err(RuntimeError::mismatch("string", &x[0])),
+        }
       }
-    }))));
+    ));
 
-    let e = Env::with(e, String::from("debug"), Arc::new(Expr::Value(
-      Type::new_rust_closure(|x: Vec<Arc<Type>>| {
-      x.iter().for_each(|item| println!("?:{:?}", item));
-      Type::unit()
-    }))));
-    e
-  }
-  pub fn default_global() -> GlobalEnv {
-    let mut e = HashMap::new();
-    e.insert(String::from("-"), Arc::new(Expr::Value(
-      Type::new_rust_closure(|x: Vec<Arc<Type>>| {
-        let mut items = x.iter();
-        let first = items.next()
-          .expect("Missing arguments, usage: (- [from: Number] [...values: Number])");
-        if let Type::Number(n) = first.borrow() {
-          Type::new_number(items.fold(*n, |acc, v| match v.borrow() {
-            Type::Number(num) => acc - num,
-            _ => panic!("Cannot sub values which aren't numbers"),
-          }))
-        } else {
-          panic!("First element must be of type number");
+    e.define("raise",
+      Type::new_builtin(Arity::Exactly(1), |x, _| {
+        Err(RuntimeError::Raised(Arc::clone(&x[0])))
+      }
+    ));
+
+    e.define("error?",
+      Type::new_builtin(Arity::Exactly(1), |x, _| {
+        Ok(Arc::new(Type::Bool(if let Type::Error(..) = x[0].borrow() { true } else { false })))
+      }
+    ));
+
+    e.define("error-message",
+      Type::new_builtin(Arity::Exactly(1), |x, _| {
+        match x[0].borrow() {
+          Type::Error(message, _) => Ok(Arc::new(Type::Str(message.to_string()))),
+          _ => Err(RuntimeError::mismatch("error", &x[0])),
         }
       }
-    ))));
-
-    e.insert(String::from("*"), Arc::new(Expr::Value(
-      Type::new_rust_closure(|x|
-        Type::new_number(x.iter().fold(1.0, |acc, elem| match elem.borrow() {
-          Type::Number(n) => acc * n,
-          _ => panic!("Cannot multiply by non-number"),
-    }))))));
-
-    e.insert(String::from("="), Arc::new(Expr::Value(
-      Type::new_rust_closure(|x| {
-        let mut items = x.iter();
-        let first = items.next().expect("Missing arguments, usage: (= [comp] [... to])");
-        Arc::new(Type::Bool(items.all(|i| i.equals(first))))
-    }))));
-
-    e.insert(String::from("hd"), Arc::new(Expr::Value(
-      Type::new_rust_closure(|x| match x.get(0) {
-        None => panic!("Missing arguments, usage: (hd [from: List])"),
-        Some(v) => if let Type::List(l) = v.borrow() {
-          match l.borrow() {
-            List::End => Arc::clone(v),
-            List::Cons(a, _) => Arc::clone(a),
-          }
-        } else {
-          panic!("Argument incorrect type, expected list, got {:?}", v)
+    ));
+
+    e.define("error-payload",
+      Type::new_builtin(Arity::Exactly(1), |x, _| {
+        match x[0].borrow() {
+          Type::Error(_, payload) => Ok(Arc::clone(payload)),
+          _ => Err(RuntimeError::mismatch("error", &x[0])),
         }
       }
-    ))));
-
-    e.insert(String::from("tl"), Arc::new(Expr::Value(
-      Type::new_rust_closure(|x| match x.get(0) {
-        None => panic!("Missing arguments, usage: (tl [from: List])"),
-        Some(v) => if let Type::List(l) = v.borrow() {
-          match l.borrow() {
-            List::End => Arc::clone(v),
-            List::Cons(_, b) => Arc::new(Type::List(Arc::clone(b))),
-          }
-        } else {
-          panic!("Argument incorrect type, expected list, got {:?}", v)
+    ));
+
+    e.define("box",
+      Type::new_builtin(Arity::Exactly(1), |x, _| {
+        Ok(Arc::new(Type::Box(Arc::new(RwLock::new(Arc::clone(&x[0]))))))
+      }
+    ));
+
+    e.define("unbox",
+      Type::new_builtin(Arity::Exactly(1), |x, _| {
+        match x[0].borrow() {
+          Type::Box(b) => Ok(Arc::clone(&b.read().unwrap())),
+          _ => Err(RuntimeError::mismatch("box", &x[0])),
         }
       }
-    ))));
+    ));
+
+    e.define("set-box!",
+      Type::new_builtin(Arity::Exactly(2), |x, _| {
+        match x[0].borrow() {
+          Type::Box(b) => *b.write().unwrap() = Arc::clone(&x[1]),
+          _ => return Err(RuntimeError::mismatch("box", &x[0])),
+        };
+        Ok(Type::unit())
+      }
+    ));
+
+    // A symbol no other symbol is equal to, for names in macro expansions which must not clash
+    e.define("gensym",
+      Type::new_builtin(Arity::Exactly(0), |_, _| {
+        let n = GENSYM_COUNT.fetch_add(1, Ordering::SeqCst);
+        Ok(Arc::new(Type::Symbol(format!("#:g{}", n))))
+      }
+    ));
+
+    predicate!(e, "number?", Type::Number(_));
+    predicate!(e, "integer?", Type::Number(n) if n.is_integer());
+    predicate!(e, "rational?", Type::Number(n) if n.to_ratio().is_some());
+    predicate!(e, "string?", Type::Str(_));
+    predicate!(e, "symbol?", Type::Symbol(_));
+    predicate!(e, "boolean?", Type::Bool(_));
+    predicate!(e, "unit?", Type::Unit);
+    predicate!(e, "list?", Type::List(_));
+    // () reads as unit, so it counts as empty along with []
+    e.define("null?",
+      Type::new_builtin(Arity::Exactly(1), |x, _| {
+        Ok(Arc::new(Type::Bool(match x[0].borrow() {
+          Type::Unit => true,
+          Type::List(l) => if let List::End = l.borrow() { true } else { false },
+          _ => false,
+        })))
+      }
+    ));
+    predicate!(e, "tuple?", Type::Tuple(..));
+    predicate!(e, "map?", Type::Map(_));
+    predicate!(e, "set?", Type::Set(_));
+    predicate!(e, "box?", Type::Box(_));
+    predicate!(e, "procedure?", Type::Closure(..) | Type::RustClosure(..) | Type::VmClosure(_));
+    // both what pair builds and a list with an item, which is a pair of its head and tail
+    e.define("pair?", Type::new_builtin(Arity::Exactly(1), |x, g_env| match x[0].borrow() {
+      Type::Tuple(..) => Ok(Arc::new(Type::Bool(true))),
+      _ => is_cons(x, g_env),
+    }));
+    // The names the compiler uses
+    predicate!(e, "bool?", Type::Bool(_));
+    predicate!(e, "fixnum?", Type::Number(Number::Int(_)));
+    predicate!(e, "fxzero?", Type::Number(Number::Int(0)));
+
+    // The name of the type of a value, as a symbol
+    e.define("type-of",
+      Type::new_builtin(Arity::Exactly(1), |x, _| {
+        Ok(Arc::new(Type::Symbol(x[0].type_name().to_string())))
+      }
+    ));
+
+    e.define("procedure-arity",
+      Type::new_builtin(Arity::Exactly(1), |x, _| {
+        match x[0].borrow() {
+          Type::Closure(_, defn) => Ok(arity_value(defn.arity())),
+          Type::VmClosure(c) => Ok(arity_value(c.proto.arity)),
+          Type::RustClosure(_, arity) => Ok(arity_value(*arity)),
+          _ => Err(RuntimeError::mismatch("function", &x[0])),
+        }
+      }
+    ));
 
     e
   }
 }
+
+// Builds a list out of its arguments
+pub fn list(x: Vec<Arc<Type>>, _: &mut GlobalEnv) -> Result<Arc<Type>, RuntimeError> {
+  Ok(Type::new_list(x))
+}
+
+pub fn hd(x: Vec<Arc<Type>>, _: &mut GlobalEnv) -> Result<Arc<Type>, RuntimeError> {
+  Arity::Exactly(1).check(x.len())?;
+  match list_of(&x[0])?.borrow() {
+    List::End => Ok(Arc::clone(&x[0])),
+    List::Cons(a, _) => Ok(Arc::clone(a)),
+  }
+}
+
+pub fn tl(x: Vec<Arc<Type>>, _: &mut GlobalEnv) -> Result<Arc<Type>, RuntimeError> {
+  Arity::Exactly(1).check(x.len())?;
+  match list_of(&x[0])?.borrow() {
+    List::End => Ok(Arc::clone(&x[0])),
+    List::Cons(_, b) => Ok(Arc::new(Type::List(Arc::clone(b)))),
+  }
+}
+
+// Builds a tuple out of its two arguments
+pub fn pair(x: Vec<Arc<Type>>, _: &mut GlobalEnv) -> Result<Arc<Type>, RuntimeError> {
+  Arity::Exactly(2).check(x.len())?;
+  Ok(Arc::new(Type::Tuple(Arc::clone(&x[0]), Arc::clone(&x[1]))))
+}
+
+pub fn fst(x: Vec<Arc<Type>>, _: &mut GlobalEnv) -> Result<Arc<Type>, RuntimeError> {
+  Arity::Exactly(1).check(x.len())?;
+  match x[0].borrow() {
+    Type::Tuple(a, _) => Ok(Arc::clone(a)),
+    _ => Err(RuntimeError::mismatch("tuple", &x[0])),
+  }
+}
+
+pub fn snd(x: Vec<Arc<Type>>, _: &mut GlobalEnv) -> Result<Arc<Type>, RuntimeError> {
+  Arity::Exactly(1).check(x.len())?;
+  match x[0].borrow() {
+    Type::Tuple(_, b) => Ok(Arc::clone(b)),
+    _ => Err(RuntimeError::mismatch("tuple", &x[0])),
+  }
+}
+
+// Whether its argument is a list with at least one item
+pub fn is_cons(x: Vec<Arc<Type>>, _: &mut GlobalEnv) -> Result<Arc<Type>, RuntimeError> {
+  Arity::Exactly(1).check(x.len())?;
+  Ok(Arc::new(Type::Bool(match x[0].borrow() {
+    Type::List(l) => if let List::Cons(..) = l.borrow() { true } else { false },
+    _ => false,
+  })))
+}
+
+pub fn is_tuple(x: Vec<Arc<Type>>, _: &mut GlobalEnv) -> Result<Arc<Type>, RuntimeError> {
+  Arity::Exactly(1).check(x.len())?;
+  Ok(Arc::new(Type::Bool(if let Type::Tuple(..) = x[0].borrow() { true } else { false })))
+}
+
+// Raised when no pattern matches a value
+pub fn no_match(x: Vec<Arc<Type>>, _: &mut GlobalEnv) -> Result<Arc<Type>, RuntimeError> {
+  Err(RuntimeError::Raised(Arc::new(Type::Error(String::from("No pattern matched"),
+    Type::new_list(x)))))
+}
+
+// Whether all of its arguments are equal to each other
+pub fn equal(x: Vec<Arc<Type>>, _: &mut GlobalEnv) -> Result<Arc<Type>, RuntimeError> {
+  Arity::AtLeast(1).check(x.len())?;
+  let mut items = x.iter();
+  let first = items.next().unwrap();
+  Ok(Arc::new(Type::Bool(items.all(|i| i.equals(first)))))
+}
+
+// Concatenates any number of lists
+pub fn append(x: Vec<Arc<Type>>, _: &mut GlobalEnv) -> Result<Arc<Type>, RuntimeError> {
+  let mut items = Vec::new();
+  for l in x.iter() {
+    let mut curr = list_of(l)?;
+    while let List::Cons(hd, tl) = curr.borrow() {
+      items.push(Arc::clone(hd));
+      curr = tl;
+    }
+  }
+  Ok(Type::new_list(items))
+}
@@ -0,0 +1,102 @@
+use ast::{Type, List};
+use lisp_parse::write_char;
+use std::fmt;
+
+// Writes a string as the literal which reads back as it
+fn write_str(f: &mut fmt::Formatter, s: &str) -> fmt::Result {
+  write!(f, "\"")?;
+  for c in s.chars() {
+    match c {
+      '"' => write!(f, "\\\"")?,
+      '\\' => write!(f, "\\\\")?,
+      '\n' => write!(f, "\\n")?,
+      '\t' => write!(f, "\\t")?,
+      '\r' => write!(f, "\\r")?,
+      '\0' => write!(f, "\\0")?,
+      c if c.is_control() => write!(f, "\\u{{{:x}}}", c as u32)?,
+      c => write!(f, "{}", c)?,
+    }
+  }
+  write!(f, "\"")
+}
+
+// Values are written as the datum which reads back as them, and those which have no such datum,
+// like functions, are written as #<...>
+impl fmt::Display for Type {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      Type::Unit => write!(f, "()"),
+      Type::Number(n) => write!(f, "{}", n),
+      Type::Str(s) => write_str(f, s),
+      Type::Char(c) => write!(f, "{}", write_char(*c)),
+      Type::Symbol(s) => write!(f, "{}", s),
+      Type::Bool(b) => write!(f, "{}", if *b { "t" } else { "f" }),
+      Type::Tuple(a, b) => write!(f, "({} . {})", a, b),
+      Type::List(l) => write!(f, "{}", l),
+      Type::Map(m) => {
+        let entries = m.entries().iter().map(|(k, v)| format!("{} {}", k, v)).collect::<Vec<_>>();
+        write!(f, "{{{}}}", entries.join(" "))
+      },
+      Type::Set(s) => {
+        let items = s.keys().iter().map(|k| k.to_string()).collect::<Vec<_>>();
+        write!(f, "#{{{}}}", items.join(" "))
+      },
+      Type::Error(message, payload) => {
+        write!(f, "#<error ")?;
+        write_str(f, message)?;
+        write!(f, " {}>", payload)
+      },
+      Type::Closure(_, defn) if defn.name.is_empty() => write!(f, "#<function>"),
+      Type::Closure(_, defn) => write!(f, "#<function {}>", defn.name),
+      Type::VmClosure(c) if c.proto.name.is_empty() => write!(f, "#<function>"),
+      Type::VmClosure(c) => write!(f, "#<function {}>", c.proto.name),
+      Type::RustClosure(..) => write!(f, "#<builtin>"),
+      Type::Box(b) => write!(f, "#<box {}>", b.read().unwrap()),
+      Type::Free(_) => write!(f, "#<free>"),
+    }
+  }
+}
+
+// The empty list is written as [], as () reads back as unit
+impl fmt::Display for List {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    let mut curr = match self {
+      List::End => return write!(f, "[]"),
+      List::Cons(hd, tl) => {
+        write!(f, "({}", hd)?;
+        tl
+      },
+    };
+    while let List::Cons(hd, tl) = &**curr {
+      write!(f, " {}", hd)?;
+      curr = tl;
+    }
+    write!(f, ")")
+  }
+}
+
+impl Type {
+  // How the value is shown to people, which is the same as how it is written except that strings
+  // and characters are shown as they are, without quotes or escapes
+  pub fn display(&self) -> String {
+    match self {
+      Type::Str(s) => s.to_string(),
+      Type::Char(c) => c.to_string(),
+      v => v.to_string(),
+    }
+  }
+}
+
+#[test]
+fn test_round_trip() {
+  use lisp_parse::parse;
+
+  let src = concat!("(1 -2/3 4.0 \"a \\\"b\\\"\\n\\u{7}\" sym t f [] (nested (list)) #x10 ",
+    "#\\( #\\x20 (1 . (a)))");
+  let v = parse(String::from(src)).unwrap()[0].to_datum().unwrap();
+  let written = v.to_string();
+  assert_eq!(written,
+    concat!("(1 -2/3 4.0 \"a \\\"b\\\"\\n\\u{7}\" sym t f [] (nested (list)) 16 #\\( #\\space ",
+      "(1 . (a)))"));
+  assert!(parse(written).unwrap()[0].to_datum().unwrap().equals(&v));
+}
@@ -1,4 +1,8 @@
 use ast::{Type, List};
+use std::sync::Arc;
+use std::mem;
+use std::hash::{Hash, Hasher};
+use std::collections::hash_map::DefaultHasher;
 
 impl Type {
   pub fn equals(&self, o: &Self) -> bool {
@@ -7,14 +11,59 @@ impl Type {
       Type::Number(a) => if let Type::Number(b) = o { a == b } else { false },
       Type::Bool(a) => if let Type::Bool(b) = o { a == b } else { false },
       Type::Str(a) => if let Type::Str(b) = o { a == b } else { false },
+      Type::Char(a) => if let Type::Char(b) = o { a == b } else { false },
+      Type::Symbol(a) => if let Type::Symbol(b) = o { a == b } else { false },
       Type::Tuple(a, b) =>
         if let Type::Tuple(c, d) = o { a.equals(c) && b.equals(d) } else { false },
       Type::List(a) => if let Type::List(b) = o { a.equals(b) } else { false },
+      Type::Map(a) => if let Type::Map(b) = o { a.equals(b) } else { false },
+      Type::Set(a) => if let Type::Set(b) = o { a.equals(b) } else { false },
+      Type::Error(a, b) =>
+        if let Type::Error(c, d) = o { a == c && b.equals(d) } else { false },
+      // boxes are only equal to themselves, as either could be changed later
+      Type::Box(a) => if let Type::Box(b) = o { Arc::ptr_eq(a, b) } else { false },
       Type::Free(_) => unimplemented!(),
         //if let Type::Free(b) = o { a.equals(b) } else { false },
       _ => false,
     }
   }
+  // A hash which is the same for values which are equal, so that numbers hash by their exact
+  // value and maps and sets hash the same whatever order their keys are in
+  pub fn hash_code(&self) -> u64 {
+    let mut h = DefaultHasher::new();
+    self.hash_into(&mut h);
+    h.finish()
+  }
+  fn hash_into<H: Hasher>(&self, h: &mut H) {
+    mem::discriminant(self).hash(h);
+    match self {
+      Type::Number(n) => n.hash(h),
+      Type::Bool(b) => b.hash(h),
+      Type::Str(s) | Type::Symbol(s) => s.hash(h),
+      Type::Char(c) => c.hash(h),
+      Type::Tuple(a, b) => {
+        a.hash_into(h);
+        b.hash_into(h);
+      },
+      Type::List(l) => {
+        let mut curr = l;
+        while let List::Cons(hd, tl) = &**curr {
+          hd.hash_into(h);
+          curr = tl;
+        }
+      },
+      Type::Error(message, payload) => {
+        message.hash(h);
+        payload.hash_into(h);
+      },
+      Type::Map(m) | Type::Set(m) => m.entries().iter()
+        .map(|(k, v)| k.hash_code().wrapping_mul(31).wrapping_add(v.hash_code()))
+        .fold(0u64, u64::wrapping_add).hash(h),
+      Type::Box(b) => (&**b as *const _ as usize).hash(h),
+      // unit needs nothing more than its kind, and the rest are never equal to anything
+      _ => (),
+    }
+  }
 }
 
 impl List {
@@ -27,3 +76,29 @@ impl List {
     }
   }
 }
+
+#[test]
+fn test_equal_values_hash_the_same() {
+  use lisp_parse::parse;
+  use number::Number;
+
+  // ints past 2^53 sit between floats which they would round to
+  let groups = vec!("1 1.0 2/2", "9007199254740993 9007199254740992.0 9007199254740992",
+    "-9007199254740993 -9007199254740992.0", "1/3 0.3333333333333333", "0.5 1/2 +inf.0 -inf.0",
+    "100000000000000000000 1e20 100000000000000000001", "{1 a 2.0 b} {2 b 1.0 a} #{1 2} #{2.0 1}",
+    "(1 . 2.0) (1.0 . 2) (1 2) (1.0 2.0)");
+  for src in groups {
+    let values: Vec<_> = parse(String::from(src)).unwrap().iter()
+      .map(|t| t.to_datum().unwrap()).collect();
+    for a in &values {
+      for b in &values {
+        if a.equals(b) {
+          assert_eq!(a.hash_code(), b.hash_code(), "{} and {} are equal", a, b);
+        }
+      }
+    }
+  }
+  let big = Type::Number(Number::Int(9007199254740993));
+  assert!(!big.equals(&Type::Number(Number::Float(9007199254740992.0))));
+  assert!(big.equals(&Type::Number(Number::Int(9007199254740992).add(&Number::Int(1)))));
+}
@@ -0,0 +1,91 @@
+use ast::Type;
+use std::sync::Arc;
+use std::borrow::Borrow;
+use std::fmt;
+use std::error::Error;
+
+// The number of arguments a function accepts
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Arity {
+  Exactly(usize),
+  AtLeast(usize),
+  Between(usize, usize),
+}
+
+impl Arity {
+  pub fn accepts(&self, n: usize) -> bool {
+    match self {
+      Arity::Exactly(m) => n == *m,
+      Arity::AtLeast(m) => n >= *m,
+      Arity::Between(min, max) => n >= *min && n <= *max,
+    }
+  }
+  // Returns an error if n arguments cannot be passed to a function of this arity
+  pub fn check(&self, n: usize) -> Result<(), RuntimeError> {
+    if self.accepts(n) { Ok(()) } else { Err(RuntimeError::Arity{expected: *self, got: n}) }
+  }
+}
+
+impl fmt::Display for Arity {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      Arity::Exactly(n) => write!(f, "{}", n),
+      Arity::AtLeast(n) => write!(f, "at least {}", n),
+      Arity::Between(min, max) => write!(f, "{} to {}", min, max),
+    }
+  }
+}
+
+#[derive(Debug, Clone)]
+pub enum RuntimeError {
+  UnboundVariable(String),
+  Arity{expected: Arity, got: usize},
+  TypeMismatch{expected: &'static str, actual: Arc<Type>},
+  NotCallable(Arc<Type>),
+  DivideByZero,
+  IndexOutOfRange{index: usize, length: usize},
+  // A value raised by the program itself
+  Raised(Arc<Type>),
+}
+
+impl RuntimeError {
+  pub fn mismatch(expected: &'static str, actual: &Arc<Type>) -> Self {
+    RuntimeError::TypeMismatch{expected, actual: Arc::clone(actual)}
+  }
+  // The value a catch clause receives for this error. Errors from the interpreter itself become
+  // an error value whose payload is a symbol naming what kind of error it was.
+  pub fn to_value(&self) -> Arc<Type> {
+    let kind = match self {
+      RuntimeError::Raised(v) => return Arc::clone(v),
+      RuntimeError::UnboundVariable(_) => "unbound-variable",
+      RuntimeError::Arity{..} => "arity",
+      RuntimeError::TypeMismatch{..} => "type-mismatch",
+      RuntimeError::NotCallable(_) => "not-callable",
+      RuntimeError::DivideByZero => "divide-by-zero",
+      RuntimeError::IndexOutOfRange{..} => "index-out-of-range",
+    };
+    Arc::new(Type::Error(self.to_string(), Arc::new(Type::Symbol(kind.to_string()))))
+  }
+}
+
+impl fmt::Display for RuntimeError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      RuntimeError::UnboundVariable(name) => write!(f, "Unbound variable {}", name),
+      RuntimeError::Arity{expected, got} =>
+        write!(f, "Expected {} arguments, {} were supplied", expected, got),
+      RuntimeError::TypeMismatch{expected, actual} =>
+        write!(f, "Expected {}, got {} {}", expected, actual.type_name(), actual),
+      RuntimeError::NotCallable(v) => write!(f, "Cannot invoke non-function {}", v),
+      RuntimeError::DivideByZero => write!(f, "Division by zero"),
+      RuntimeError::IndexOutOfRange{index, length} =>
+        write!(f, "Index {} is out of range for a list of length {}", index, length),
+      RuntimeError::Raised(v) => match v.borrow() {
+        Type::Error(message, _) => write!(f, "{}", message),
+        _ => write!(f, "Uncaught {}", v),
+      },
+    }
+  }
+}
+
+impl Error for RuntimeError {}
@@ -0,0 +1,364 @@
+use lisp_parse::{Token, Span, Brace};
+use to_ast::{SyntaxError, is_param};
+use ast::{Expr, Type, GlobalEnv, List};
+use std::sync::Arc;
+use std::borrow::Borrow;
+
+fn error<T>(t: &Token, message: String) -> Result<T, SyntaxError> {
+  Err(SyntaxError{message, span: t.span()})
+}
+
+// Converts data returned by a macro back into code, giving it the span of the form it replaced
+fn from_datum(v: &Arc<Type>, span: Span) -> Option<Token> {
+  Some(match v.borrow() {
+    Type::Symbol(s) => Token::Word(s.to_string(), span),
+    Type::Number(n) => Token::Word(n.to_string(), span),
+    Type::Char(_) => Token::Word(v.to_string(), span),
+    Type::Bool(b) => Token::Word(String::from(if *b { "t" } else { "f" }), span),
+    Type::Str(s) => Token::Str(s.to_string(), span),
+    Type::Unit => Token::Group(Vec::new(), span),
+    Type::Map(m) => {
+      let mut items = Vec::new();
+      for (k, v) in m.entries() {
+        items.push(from_datum(&k, span)?);
+        items.push(from_datum(&v, span)?);
+      }
+      Token::Braced(Brace::Map, items, span)
+    },
+    Type::Set(s) => Token::Braced(Brace::Set,
+      s.keys().iter().map(|k| from_datum(k, span)).collect::<Option<_>>()?, span),
+    Type::Tuple(a, b) =>
+      Token::Group(vec!(from_datum(a, span)?, Token::Word(String::from("."), span),
+        from_datum(b, span)?), span),
+    Type::List(l) => {
+      let mut items = Vec::new();
+      let mut curr: &Arc<List> = l;
+      while let List::Cons(hd, tl) = curr.borrow() {
+        items.push(from_datum(hd, span)?);
+        curr = tl;
+      }
+      match items.len() {
+        0 => Token::Word(String::from("[]"), span),
+        _ => Token::Group(items, span),
+      }
+    },
+    _ => return None,
+  })
+}
+
+fn head(t: &Token) -> Option<(&str, &[Token])> {
+  match t {
+    Token::Group(g, _) => match g.get(0) {
+      Some(Token::Word(s, _)) => Some((s, &g[1..])),
+      _ => None,
+    },
+    _ => None,
+  }
+}
+
+// The macro the form calls, unless its name is rebound where the form is
+fn macro_of(t: &Token, bound: &[String], g_env: &GlobalEnv) -> Option<(String, Arc<Type>)> {
+  match head(t) {
+    Some((name, _)) if !bound.iter().any(|b| b == name) =>
+      g_env.get_macro(name).map(|m| (name.to_string(), m)),
+    _ => None,
+  }
+}
+
+// Expands the form until it is no longer a call to a macro, leaving its operands alone
+fn expand_head(t: &Token, bound: &[String], g_env: &mut GlobalEnv) -> Result<Token, SyntaxError> {
+  let mut t = t.clone();
+  while let Some((name, expander)) = macro_of(&t, bound, g_env) {
+    let args = head(&t).map_or(&[][..], |(_, operands)| operands).iter()
+      .map(|o| o.to_datum()).collect::<Result<_, _>>()?;
+    let expanded = match Expr::apply(&expander, args, g_env) {
+      Ok(v) => v,
+      Err(e) => return error(&t, format!("Error expanding {}: {}", name, e)),
+    };
+    t = match from_datum(&expanded, t.span()) {
+      Some(code) => code,
+      None => return error(&t, format!("{} expanded to {}, which is not code", name, expanded)),
+    };
+  }
+  Ok(t)
+}
+
+// Parses (defmacro name params... body) and makes the function it describes the expander for name
+fn defmacro(t: &Token, g: &[Token], g_env: &mut GlobalEnv) -> Result<Token, SyntaxError> {
+  if g.len() < 3 {
+    return error(t, String::from("Defmacro must have a name and body"))
+  }
+  let mut defn = vec!(Token::Word(String::from("defn"), g[0].span()));
+  defn.extend(g[1..].iter().cloned());
+  let expander = expand(&Token::Group(defn, t.span()), g_env)?.to_ast()?;
+  let expander = match expander.resolve(g_env).and_then(|e| e.eval(Arc::new(None), g_env)) {
+    Ok(v) => v.to_type(),
+    Err(e) => return error(t, format!("Error defining macro: {}", e)),
+  };
+  let name = match &g[1] {
+    Token::Word(name, _) => name,
+    _ => unreachable!("defn checks that it has a name"),
+  };
+  g_env.define_macro(name, expander);
+  Ok(Token::Group(vec!(Token::Word(String::from("quote"), t.span()), g[1].clone()), t.span()))
+}
+
+// Expands the parts of a quasiquoted datum which are unquoted
+fn quasiquote(t: &Token, depth: usize, bound: &[String], g_env: &mut GlobalEnv)
+  -> Result<Token, SyntaxError> {
+  let (g, span) = match t {
+    Token::Group(g, span) => (g, *span),
+    Token::Braced(brace, items, span) => return Ok(Token::Braced(*brace, items.iter()
+      .map(|item| quasiquote(item, depth, bound, g_env)).collect::<Result<_, _>>()?, *span)),
+    _ => return Ok(t.clone()),
+  };
+  let depth = match head(t) {
+    Some(("unquote", _)) | Some(("unquote-splicing", _)) if depth == 0 =>
+      return expand_in(t, bound, g_env),
+    Some(("unquote", _)) | Some(("unquote-splicing", _)) => depth - 1,
+    Some(("quasiquote", _)) => depth + 1,
+    _ => depth,
+  };
+  Ok(Token::Group(g.iter().map(|item| quasiquote(item, depth, bound, g_env))
+    .collect::<Result<_, _>>()?, span))
+}
+
+// Adds the names a pattern or param binds, which hide any macros of the same name in their scope
+fn bind_names(t: &Token, scope: &mut Vec<String>) {
+  match (t, head(t)) {
+    (Token::Word(s, _), _) => scope.push(s.trim_start_matches('&').to_string()),
+    (_, Some(("quote", _))) => (),
+    (_, Some(("tuple", ps))) | (_, Some(("pair", ps))) => for p in ps { bind_names(p, scope) },
+    (Token::Group(g, _), _) => for p in g { bind_names(p, scope) },
+    _ => (),
+  }
+}
+
+fn first(t: &Token) -> Option<&Token> {
+  match t {
+    Token::Group(g, _) => g.get(0),
+    _ => None,
+  }
+}
+
+fn expand_all(ts: &[Token], bound: &[String], g_env: &mut GlobalEnv)
+  -> Result<Vec<Token>, SyntaxError> {
+  ts.iter().map(|t| expand_in(t, bound, g_env)).collect()
+}
+
+// Expands the parts of a group which is not a call, such as a clause or binding, after the first
+// keep of them
+fn expand_parts(t: &Token, keep: usize, bound: &[String], g_env: &mut GlobalEnv)
+  -> Result<Token, SyntaxError> {
+  match t {
+    Token::Group(g, span) if g.len() >= keep => {
+      let mut parts = g[..keep].to_vec();
+      parts.extend(expand_all(&g[keep..], bound, g_env)?);
+      Ok(Token::Group(parts, *span))
+    },
+    _ => Ok(t.clone()),
+  }
+}
+
+// Expands the values of the bindings of a let, let* or letrec, adding the names they bind to the
+// scope. Each value of a let* can see the names bound before it, and those of a letrec all of them.
+fn bindings(t: &Token, form: &str, scope: &mut Vec<String>, g_env: &mut GlobalEnv)
+  -> Result<Token, SyntaxError> {
+  let (bs, span) = match t {
+    Token::Group(bs, span) => (bs, *span),
+    _ => return Ok(t.clone()),
+  };
+  let outer = scope.clone();
+  let rec = form.starts_with("letrec");
+  if rec {
+    bs.iter().filter_map(first).for_each(|p| bind_names(p, scope));
+  }
+  let mut expanded = Vec::new();
+  for b in bs {
+    let sees = if form == "let" { outer.clone() } else { scope.clone() };
+    expanded.push(expand_parts(b, 1, &sees, g_env)?);
+    match first(b) {
+      Some(p) if !rec => bind_names(p, scope),
+      _ => (),
+    }
+  }
+  Ok(Token::Group(expanded, span))
+}
+
+// Expands the clauses of a for, each of which can see the names bound by the ones before it
+fn for_clauses(cs: &[Token], scope: &mut Vec<String>, g_env: &mut GlobalEnv)
+  -> Result<Vec<Token>, SyntaxError> {
+  let mut expanded = Vec::new();
+  for c in cs {
+    expanded.push(match c {
+      Token::Group(parts, _) => match (head(c), parts.len()) {
+        (Some(("when", _)), 2) | (Some(("unless", _)), 2) => expand_parts(c, 1, scope, g_env)?,
+        (Some(("let", _)), 3) => {
+          let c = expand_parts(c, 2, scope, g_env)?;
+          bind_names(&parts[1], scope);
+          c
+        },
+        (_, 2) => {
+          let c = expand_parts(c, 1, scope, g_env)?;
+          bind_names(&parts[0], scope);
+          c
+        },
+        _ => c.clone(),
+      },
+      _ => c.clone(),
+    });
+  }
+  Ok(expanded)
+}
+
+// Replaces every call to a macro within the token with the code it expands to. Macros are defined
+// as they are reached, so a macro can be used by anything which comes after its definition.
+pub fn expand(t: &Token, g_env: &mut GlobalEnv) -> Result<Token, SyntaxError> {
+  expand_in(t, &[], g_env)
+}
+
+// Expands the token where the names in bound are rebound, so are not calls to macros. Patterns
+// and the names bound by them are left as they are.
+fn expand_in(t: &Token, bound: &[String], g_env: &mut GlobalEnv) -> Result<Token, SyntaxError> {
+  let t = expand_head(t, bound, g_env)?;
+  let (g, span) = match &t {
+    Token::Group(g, span) => (g, *span),
+    Token::Braced(brace, items, span) =>
+      return Ok(Token::Braced(*brace, expand_all(items, bound, g_env)?, *span)),
+    _ => return Ok(t),
+  };
+  let mut scope = bound.to_vec();
+  let items = match head(&t) {
+    Some(("quote", _)) => return Ok(t.clone()),
+    Some(("quasiquote", [datum])) => vec!(g[0].clone(), match datum {
+      Token::Group(items, span) => Token::Group(items.iter()
+        .map(|i| quasiquote(i, 0, bound, g_env)).collect::<Result<_, _>>()?, *span),
+      Token::Braced(..) => quasiquote(datum, 0, bound, g_env)?,
+      _ => datum.clone(),
+    }),
+    Some(("defmacro", _)) => return defmacro(&t, g, g_env),
+    // (macroexpand 'form) is replaced by the quoted expansion of the form
+    Some(("macroexpand", [quoted])) => match head(quoted) {
+      Some(("quote", [form])) => vec!(Token::Word(String::from("quote"), span),
+        expand_head(form, bound, g_env)?),
+      _ => return error(&t, String::from("Macroexpand takes a quoted form")),
+    },
+    Some(("macroexpand", _)) =>
+      return error(&t, String::from("Macroexpand takes exactly one operand")),
+    Some(("lambda", [ps @ Token::Group(..), ..])) | Some(("fn", [ps @ Token::Group(..), ..])) => {
+      bind_names(ps, &mut scope);
+      let mut items = g[..2].to_vec();
+      items.extend(expand_all(&g[2..], &scope, g_env)?);
+      items
+    },
+    // the params are split from the body as to_ast does, so are left as they are
+    Some(("defn", [name, ..])) if g.len() > 2 => {
+      let start = g[2..(g.len()-1)].iter().position(|p| !is_param(p))
+        .map_or(g.len() - 1, |i| i + 2);
+      bind_names(name, &mut scope);
+      g[2..start].iter().for_each(|p| bind_names(p, &mut scope));
+      let mut items = g[..start].to_vec();
+      items.extend(expand_all(&g[start..], &scope, g_env)?);
+      items
+    },
+    Some((form @ "let", [bs @ Token::Group(..), ..])) | Some((form @ "let*", [bs, ..])) |
+      Some((form @ "letrec", [bs, ..])) | Some((form @ "letrec*", [bs, ..])) => {
+      let bs = bindings(bs, form, &mut scope, g_env)?;
+      let mut items = vec!(g[0].clone(), bs);
+      items.extend(expand_all(&g[2..], &scope, g_env)?);
+      items
+    },
+    Some(("let", [name, ..])) => {
+      let mut items = g[..2].to_vec();
+      items.extend(expand_all(&g[2..g.len().min(3)], bound, g_env)?);
+      bind_names(name, &mut scope);
+      items.extend(expand_all(&g[g.len().min(3)..], &scope, g_env)?);
+      items
+    },
+    Some(("match", [value, ..])) => {
+      let mut items = vec!(g[0].clone(), expand_in(value, bound, g_env)?);
+      for arm in &g[2..] {
+        let mut scope = bound.to_vec();
+        if let Some(p) = first(arm) {
+          bind_names(p, &mut scope);
+        }
+        items.push(expand_parts(arm, 1, &scope, g_env)?);
+      }
+      items
+    },
+    Some(("for", [Token::Group(cs, cs_span), ..])) if g.len() > 2 => {
+      let mut items = vec!(g[0].clone(),
+        Token::Group(for_clauses(cs, &mut scope, g_env)?, *cs_span));
+      items.extend(for_clauses(&g[2..(g.len()-1)], &mut scope, g_env)?);
+      items.push(expand_in(&g[g.len()-1], &scope, g_env)?);
+      items
+    },
+    Some(("try", [body, ..])) => {
+      let mut items = vec!(g[0].clone(), expand_in(body, bound, g_env)?);
+      for c in &g[2..] {
+        items.push(match (head(c), c) {
+          (Some(("catch", [name, _])), _) => {
+            let mut scope = bound.to_vec();
+            bind_names(name, &mut scope);
+            expand_parts(c, 2, &scope, g_env)?
+          },
+          (Some(("finally", _)), _) => expand_parts(c, 1, bound, g_env)?,
+          _ => c.clone(),
+        });
+      }
+      items
+    },
+    Some(("cond", clauses)) => Some(g[0].clone()).into_iter().chain(clauses.iter()
+      .map(|c| expand_parts(c, 0, bound, g_env)).collect::<Result<Vec<_>, _>>()?).collect(),
+    // the datums of each clause are left as they are
+    Some(("case", [key, ..])) => {
+      let mut items = vec!(g[0].clone(), expand_in(key, bound, g_env)?);
+      for c in &g[2..] {
+        items.push(expand_parts(c, 1, bound, g_env)?);
+      }
+      items
+    },
+    _ => expand_all(g, bound, g_env)?,
+  };
+  Ok(Token::Group(items, span))
+}
+
+#[test]
+fn test_macros() {
+  use lisp_parse::parse;
+  use ast::Env;
+
+  let mut g_env = Env::default_global();
+  let mut eval = |src: &str| {
+    let mut last = Type::unit();
+    for t in parse(String::from(src)).unwrap() {
+      let expr = expand(&t, &mut g_env).unwrap().to_ast().unwrap().resolve(&mut g_env).unwrap();
+      last = expr.eval(Env::default(), &mut g_env).unwrap().to_type();
+    }
+    last
+  };
+
+  assert!(eval("(defmacro swap-if c a b `(if ,c ,b ,a)) (swap-if t 1 2)")
+    .equals(&Type::new_number(2.0)));
+  assert!(eval("(macroexpand '(swap-if t 1 (swap-if f 2 3)))")
+    .equals(&eval("'(if t (swap-if f 2 3) 1)")));
+  assert!(eval("`(swap-if ,(swap-if f 1 2) 3 4)").equals(&eval("'(swap-if 1 3 4)")));
+
+  eval("(defmacro my-or a b (let tmp (gensym) `(let ,tmp ,a (if ,tmp ,tmp ,b))))");
+  assert!(eval("(let tmp 5 (my-or f tmp))").equals(&Type::new_number(5.0)));
+
+  eval("(defmacro one-map k `{,k (my-or f ,k)})");
+  assert!(eval("(get (one-map 2) 2)").equals(&Type::new_number(2.0)));
+  assert!(eval("(macroexpand '(one-map 2))").equals(&eval("'{2 (my-or f 2)}")));
+
+  // names bound locally hide macros, and patterns are not expanded
+  eval("(defmacro twice x `(+ ,x ,x))");
+  assert!(eval("((lambda (twice a) (twice a)) (lambda (x) (* x 10)) 1)")
+    .equals(&Type::new_number(10.0)));
+  assert!(eval("(let twice (lambda (x) x) (twice 3))").equals(&Type::new_number(3.0)));
+  assert!(eval("(let* ((twice 4) (a (+ twice 1))) a)").equals(&Type::new_number(5.0)));
+  assert!(eval("(match '(1 2) ((twice a) (+ twice a)))").equals(&Type::new_number(3.0)));
+  assert!(eval("(let g (defn g (twice a) (+ twice a))) (g '(1 2))")
+    .equals(&Type::new_number(3.0)));
+  assert!(eval("(cond ((= 1 (twice 1)) 1) (t (twice 2)))").equals(&Type::new_number(4.0)));
+}
@@ -0,0 +1,242 @@
+use ast::Type;
+use std::sync::Arc;
+
+// How many bits of the hash pick a child at each level of the trie
+const BITS: u32 = 5;
+
+// A persistent hash map, as a hash array mapped trie. Adding or removing a key copies only the
+// nodes on the path to it, so that the other nodes are shared between versions like the tails
+// of a List are.
+#[derive(Debug, Clone)]
+pub struct Map {
+  root: Arc<Node>,
+  len: usize,
+}
+
+#[derive(Debug, Clone)]
+enum Node {
+  // The bitmap has a bit set for each child which is present, which are kept in order
+  Branch(u32, Vec<Entry>),
+  // Keys whose hashes are entirely the same
+  Collision(Vec<(Arc<Type>, Arc<Type>)>),
+}
+
+#[derive(Debug, Clone)]
+enum Entry {
+  Leaf(u64, Arc<Type>, Arc<Type>),
+  Child(Arc<Node>),
+}
+
+// The bit for the child the hash belongs to at the given depth
+fn bit(hash: u64, shift: u32) -> u32 {
+  1 << ((hash >> shift) & ((1 << BITS) - 1))
+}
+
+// Where the child for the bit is kept among the children which are present
+fn index(bitmap: u32, bit: u32) -> usize {
+  (bitmap & (bit - 1)).count_ones() as usize
+}
+
+// If the node holds a single key, gives it back so that it can be kept in its parent instead
+fn lone_leaf(node: &Node, hash: u64) -> Option<Entry> {
+  match node {
+    Node::Branch(_, entries) if entries.len() == 1 => match &entries[0] {
+      Entry::Leaf(..) => Some(entries[0].clone()),
+      Entry::Child(_) => None,
+    },
+    Node::Collision(pairs) if pairs.len() == 1 =>
+      Some(Entry::Leaf(hash, Arc::clone(&pairs[0].0), Arc::clone(&pairs[0].1))),
+    _ => None,
+  }
+}
+
+impl Node {
+  // A node holding two keys with different hashes at this depth, or the same hash entirely
+  fn two(shift: u32, a: Entry, b: Entry) -> Node {
+    let (ha, hb) = match (&a, &b) {
+      (Entry::Leaf(ha, ..), Entry::Leaf(hb, ..)) => (*ha, *hb),
+      _ => unreachable!(),
+    };
+    if shift >= 64 {
+      return match (a, b) {
+        (Entry::Leaf(_, ka, va), Entry::Leaf(_, kb, vb)) =>
+          Node::Collision(vec!((ka, va), (kb, vb))),
+        _ => unreachable!(),
+      }
+    }
+    let (ba, bb) = (bit(ha, shift), bit(hb, shift));
+    if ba == bb {
+      Node::Branch(ba, vec!(Entry::Child(Arc::new(Node::two(shift + BITS, a, b)))))
+    } else if ba < bb {
+      Node::Branch(ba | bb, vec!(a, b))
+    } else {
+      Node::Branch(ba | bb, vec!(b, a))
+    }
+  }
+  fn get(&self, hash: u64, shift: u32, key: &Type) -> Option<&Arc<Type>> {
+    match self {
+      Node::Branch(bitmap, entries) => {
+        let bit = bit(hash, shift);
+        if bitmap & bit == 0 {
+          return None
+        }
+        match &entries[index(*bitmap, bit)] {
+          Entry::Leaf(_, k, v) => if k.equals(key) { Some(v) } else { None },
+          Entry::Child(node) => node.get(hash, shift + BITS, key),
+        }
+      },
+      Node::Collision(pairs) => pairs.iter().find(|(k, _)| k.equals(key)).map(|(_, v)| v),
+    }
+  }
+  // Gives back the node with the key set to the value, and whether the key is a new one
+  fn insert(&self, hash: u64, shift: u32, key: Arc<Type>, value: Arc<Type>) -> (Node, bool) {
+    match self {
+      Node::Branch(bitmap, entries) => {
+        let bit = bit(hash, shift);
+        let i = index(*bitmap, bit);
+        let mut entries = entries.clone();
+        if bitmap & bit == 0 {
+          entries.insert(i, Entry::Leaf(hash, key, value));
+          return (Node::Branch(bitmap | bit, entries), true)
+        }
+        let (entry, added) = match &entries[i] {
+          Entry::Leaf(_, k, _) if k.equals(&key) => (Entry::Leaf(hash, key, value), false),
+          leaf @ Entry::Leaf(..) => (Entry::Child(Arc::new(Node::two(shift + BITS, leaf.clone(),
+            Entry::Leaf(hash, key, value)))), true),
+          Entry::Child(node) => {
+            let (node, added) = node.insert(hash, shift + BITS, key, value);
+            (Entry::Child(Arc::new(node)), added)
+          },
+        };
+        entries[i] = entry;
+        (Node::Branch(*bitmap, entries), added)
+      },
+      Node::Collision(pairs) => {
+        let mut pairs = pairs.clone();
+        match pairs.iter().position(|(k, _)| k.equals(&key)) {
+          Some(i) => {
+            pairs[i] = (key, value);
+            (Node::Collision(pairs), false)
+          },
+          None => {
+            pairs.push((key, value));
+            (Node::Collision(pairs), true)
+          },
+        }
+      },
+    }
+  }
+  // Gives back the node without the key, or None if the key is not in it
+  fn remove(&self, hash: u64, shift: u32, key: &Type) -> Option<Node> {
+    match self {
+      Node::Branch(bitmap, entries) => {
+        let bit = bit(hash, shift);
+        if bitmap & bit == 0 {
+          return None
+        }
+        let i = index(*bitmap, bit);
+        let mut entries = entries.clone();
+        let replacement = match &entries[i] {
+          Entry::Leaf(_, k, _) => if k.equals(key) { None } else { return None },
+          Entry::Child(node) => {
+            let node = node.remove(hash, shift + BITS, key)?;
+            Some(lone_leaf(&node, hash).unwrap_or_else(|| Entry::Child(Arc::new(node))))
+          },
+        };
+        Some(match replacement {
+          Some(entry) => {
+            entries[i] = entry;
+            Node::Branch(*bitmap, entries)
+          },
+          None => {
+            entries.remove(i);
+            Node::Branch(bitmap & !bit, entries)
+          },
+        })
+      },
+      Node::Collision(pairs) => {
+        let i = pairs.iter().position(|(k, _)| k.equals(key))?;
+        let mut pairs = pairs.clone();
+        pairs.remove(i);
+        Some(Node::Collision(pairs))
+      },
+    }
+  }
+  fn entries(&self, out: &mut Vec<(Arc<Type>, Arc<Type>)>) {
+    match self {
+      Node::Branch(_, entries) => for entry in entries {
+        match entry {
+          Entry::Leaf(_, k, v) => out.push((Arc::clone(k), Arc::clone(v))),
+          Entry::Child(node) => node.entries(out),
+        }
+      },
+      Node::Collision(pairs) => out.extend(pairs.iter().cloned()),
+    }
+  }
+}
+
+impl Map {
+  pub fn new() -> Map {
+    Map{root: Arc::new(Node::Branch(0, Vec::new())), len: 0}
+  }
+  pub fn len(&self) -> usize {
+    self.len
+  }
+  pub fn is_empty(&self) -> bool {
+    self.len == 0
+  }
+  pub fn get(&self, key: &Type) -> Option<&Arc<Type>> {
+    self.root.get(key.hash_code(), 0, key)
+  }
+  pub fn insert(&self, key: Arc<Type>, value: Arc<Type>) -> Map {
+    let (root, added) = self.root.insert(key.hash_code(), 0, key, value);
+    Map{root: Arc::new(root), len: if added { self.len + 1 } else { self.len }}
+  }
+  pub fn remove(&self, key: &Type) -> Map {
+    match self.root.remove(key.hash_code(), 0, key) {
+      Some(root) => Map{root: Arc::new(root), len: self.len - 1},
+      None => self.clone(),
+    }
+  }
+  // The keys along with their values, in the order of their hashes
+  pub fn entries(&self) -> Vec<(Arc<Type>, Arc<Type>)> {
+    let mut out = Vec::with_capacity(self.len);
+    self.root.entries(&mut out);
+    out
+  }
+  pub fn keys(&self) -> Vec<Arc<Type>> {
+    self.entries().into_iter().map(|(k, _)| k).collect()
+  }
+  // Whether both maps have the same keys, with values which are equal
+  pub fn equals(&self, o: &Map) -> bool {
+    self.len == o.len && self.entries().iter().all(|(k, v)| match o.get(k) {
+      Some(w) => v.equals(w),
+      None => false,
+    })
+  }
+}
+
+impl Default for Map {
+  fn default() -> Map {
+    Map::new()
+  }
+}
+
+#[test]
+fn test_insert_remove() {
+  use number::Number;
+
+  let mut m = Map::new();
+  for i in 0..1000 {
+    m = m.insert(Type::new_number(i), Type::new_number(i * 2));
+  }
+  let removed = (0..1000).filter(|i| i % 3 == 0)
+    .fold(m.clone(), |m, i| m.remove(&Type::Number(Number::Int(i))));
+  assert!(Map::default().is_empty() && !m.is_empty());
+  assert_eq!(m.len(), 1000);
+  assert_eq!(removed.len(), 666);
+  assert!(m.get(&Type::Number(Number::Float(3.0))).unwrap().equals(&Type::new_number(6)));
+  assert!(removed.get(&Type::Number(Number::Int(3))).is_none());
+  assert!(removed.get(&Type::Number(Number::Int(4))).is_some());
+  assert!(removed.remove(&Type::Number(Number::Int(3))).equals(&removed));
+}
@@ -2,10 +2,28 @@
 
 #[macro_use]
 extern crate lazy_static;
+extern crate num_bigint;
+extern crate num_rational;
+extern crate num_traits;
+extern crate num_integer;
+#[cfg(test)]
+extern crate quickcheck;
 
 pub mod ast;
+pub mod error;
+pub mod number;
 pub mod lisp_parse;
 pub mod to_ast;
+pub mod expand;
+pub mod pattern;
+pub mod resolve;
 pub mod default_env;
+pub mod math;
+pub mod list;
+pub mod hamt;
+pub mod map;
+pub mod string;
 pub mod equals;
+pub mod display;
 pub mod compile;
+pub mod vm;
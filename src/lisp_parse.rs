@@ -1,68 +1,418 @@
-#[derive(Debug)]
+use std::fmt;
+use std::error::Error;
+
+// A location in the source text, the byte range covered along with the 1-indexed line and
+// column of its first character.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Span {
+  pub start: usize,
+  pub end: usize,
+  pub line: usize,
+  pub col: usize,
+}
+
+#[derive(Debug, Clone)]
 pub enum Token {
-  Word(String),
-  Group(Vec<Token>),
+  Word(String, Span),
+  Str(String, Span),
+  Group(Vec<Token>, Span),
+  // The items of a map or set literal, which are keys and values in turn for a map
+  Braced(Brace, Vec<Token>, Span),
+}
+
+// The collections which are written between braces, {k v ...} for a map and #{x ...} for a set
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Brace {
+  Map,
+  Set,
+}
+
+impl Brace {
+  pub fn open(&self) -> &'static str {
+    match self {
+      Brace::Map => "{",
+      Brace::Set => "#{",
+    }
+  }
 }
 
 impl Token {
-  fn add_next(&mut self, next: Token) {
+  pub fn span(&self) -> Span {
     match self {
-      Token::Word(_) => panic!("Cannot add next to singleton"),
-      Token::Group(ref mut tg) => tg.push(next),
+      Token::Word(_, span) | Token::Str(_, span) | Token::Group(_, span) |
+      Token::Braced(_, _, span) => *span,
     }
   }
-  fn init_group() -> Self {
-    Token::Group(Vec::new())
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ParseError {
+  ExtraRightParen(Span),
+  UnmatchedLeftParen(Span),
+  UnterminatedString(Span),
+  InvalidEscape(Span),
+  UnterminatedComment(Span),
+  DanglingQuote(Span),
+  MismatchedBrace(Span),
+}
+
+impl ParseError {
+  pub fn span(&self) -> Span {
+    match self {
+      ParseError::ExtraRightParen(span) | ParseError::UnmatchedLeftParen(span) |
+      ParseError::UnterminatedString(span) | ParseError::InvalidEscape(span) |
+      ParseError::UnterminatedComment(span) | ParseError::DanglingQuote(span) |
+      ParseError::MismatchedBrace(span) => *span,
+    }
   }
 }
 
-pub fn parse(body: String) -> Vec<Token> {
-  let to_parse = body.trim();
-  let mut done: Vec<Token> = Vec::new();
-  let mut buf: Vec<Token> = Vec::new();
-  let mut curr = String::from("");
-  for c in to_parse.chars() {
-    match c {
-      '(' => buf.push(Token::init_group()),
-      ')' => {
-        if curr.len() > 0 {
-          let len = buf.len() - 1;
-          buf[len].add_next(Token::Word(curr.clone()));
-          curr = String::from("");
+impl fmt::Display for ParseError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    let span = self.span();
+    match self {
+      ParseError::ExtraRightParen(_) => write!(f, "Extra right parens"),
+      ParseError::UnmatchedLeftParen(_) => write!(f, "Unmatched left parens"),
+      ParseError::UnterminatedString(_) => write!(f, "Unterminated string"),
+      ParseError::InvalidEscape(_) => write!(f, "Invalid escape sequence"),
+      ParseError::UnterminatedComment(_) => write!(f, "Unterminated block comment"),
+      ParseError::DanglingQuote(_) => write!(f, "Quote must be followed by a datum"),
+      ParseError::MismatchedBrace(_) => write!(f, "Mismatched brace"),
+    }?;
+    write!(f, " at line {}, column {}", span.line, span.col)
+  }
+}
+
+impl Error for ParseError {}
+
+#[derive(Debug)]
+enum Lexeme {
+  Open(Span),
+  Close(Span),
+  // `{` or `#{`, which open a map or set literal
+  OpenBrace(Brace, Span),
+  CloseBrace(Span),
+  Atom(String, Span),
+  Str(String, Span),
+  // `#;`, which comments out the datum following it
+  DatumComment,
+  // Reader shorthand such as `'`, which wraps the following datum in the named form
+  Prefix(&'static str, Span),
+}
+
+// Splits source text into parens, atoms and strings, keeping track of where each one came from.
+// Whitespace, `;` line comments and `#| |#` block comments are skipped over.
+struct Lexer<'a> {
+  src: &'a str,
+  offset: usize,
+  line: usize,
+  col: usize,
+}
+
+impl<'a> Lexer<'a> {
+  fn new(src: &'a str) -> Self {
+    Lexer{src, offset: 0, line: 1, col: 1}
+  }
+  fn peek(&self) -> Option<char> {
+    self.src[self.offset..].chars().next()
+  }
+  fn bump(&mut self) -> Option<char> {
+    let c = self.peek()?;
+    self.offset += c.len_utf8();
+    if c == '\n' {
+      self.line += 1;
+      self.col = 1;
+    } else {
+      self.col += 1;
+    }
+    Some(c)
+  }
+  fn span_from(&self, start: usize, line: usize, col: usize) -> Span {
+    Span{start, end: self.offset, line, col}
+  }
+  fn starts_with(&self, s: &str) -> bool {
+    self.src[self.offset..].starts_with(s)
+  }
+  fn skip_trivia(&mut self) -> Result<(), ParseError> {
+    loop {
+      match self.peek() {
+        Some(c) if c.is_whitespace() => { self.bump(); },
+        Some(';') => while self.peek().map_or(false, |c| c != '\n') {
+          self.bump();
+        },
+        Some('#') if self.starts_with("#|") => self.block_comment()?,
+        _ => return Ok(()),
+      }
+    }
+  }
+  // Skips a block comment, which may contain other block comments.
+  fn block_comment(&mut self) -> Result<(), ParseError> {
+    let (start, line, col) = (self.offset, self.line, self.col);
+    let mut depth = 0;
+    loop {
+      if self.starts_with("#|") {
+        depth += 1;
+        self.bump();
+      } else if self.starts_with("|#") {
+        depth -= 1;
+        self.bump();
+      }
+      if self.bump().is_none() {
+        return Err(ParseError::UnterminatedComment(self.span_from(start, line, col)))
+      }
+      if depth == 0 {
+        return Ok(())
+      }
+    }
+  }
+  // Reads the rest of a string literal after its opening quote, unescaping as it goes.
+  fn string(&mut self, start: usize, line: usize, col: usize) -> Result<Lexeme, ParseError> {
+    let mut out = String::new();
+    loop {
+      let (esc_start, esc_line, esc_col) = (self.offset, self.line, self.col);
+      match self.bump() {
+        None => return Err(ParseError::UnterminatedString(self.span_from(start, line, col))),
+        Some('"') => return Ok(Lexeme::Str(out, self.span_from(start, line, col))),
+        Some('\\') => {
+          match self.escape() {
+            Some(c) => out.push(c),
+            None => return Err(ParseError::InvalidEscape(
+              self.span_from(esc_start, esc_line, esc_col))),
+          }
+        },
+        Some(c) => out.push(c),
+      }
+    }
+  }
+  fn escape(&mut self) -> Option<char> {
+    match self.bump()? {
+      'n' => Some('\n'),
+      't' => Some('\t'),
+      'r' => Some('\r'),
+      '0' => Some('\0'),
+      '"' => Some('"'),
+      '\\' => Some('\\'),
+      'u' => {
+        if self.bump()? != '{' {
+          return None
         }
-        let completed = buf.pop().expect("Extra right parens");
-        if buf.is_empty() {
-          done.push(completed)
-        } else {
-          let len = buf.len() - 1;
-          buf.get_mut(len).expect("Extra right parens").add_next(completed);
+        let digits_start = self.offset;
+        while self.peek().map_or(false, |c| c.is_digit(16)) {
+          self.bump();
         }
+        let digits = &self.src[digits_start..self.offset];
+        if self.bump()? != '}' || digits.is_empty() || digits.len() > 6 {
+          return None
+        }
+        u32::from_str_radix(digits, 16).ok().and_then(::std::char::from_u32)
+      },
+      _ => None,
+    }
+  }
+}
+
+// The characters which are written by name after #\, rather than as themselves
+const CHAR_NAMES: [(&str, char); 6] = [("space", ' '), ("newline", '\n'), ("tab", '\t'),
+  ("return", '\r'), ("nul", '\0'), ("delete", '\u{7f}')];
+
+// Reads a character literal: #\a is the character itself, #\space one of the named characters,
+// and #\x41 the character with that hex code
+pub fn read_char(word: &str) -> Option<char> {
+  if !word.starts_with("#\\") {
+    return None
+  }
+  let rest = &word[2..];
+  let mut chars = rest.chars();
+  match (chars.next(), chars.next()) {
+    (Some(c), None) => Some(c),
+    (Some('x'), Some(_)) if rest[1..].chars().all(|c| c.is_digit(16)) =>
+      u32::from_str_radix(&rest[1..], 16).ok().and_then(::std::char::from_u32),
+    _ => CHAR_NAMES.iter().find(|(name, _)| *name == rest).map(|(_, c)| *c),
+  }
+}
+
+// Writes a character as the literal which reads back as it
+pub fn write_char(c: char) -> String {
+  match CHAR_NAMES.iter().find(|(_, named)| *named == c) {
+    Some((name, _)) => format!("#\\{}", name),
+    None if c.is_control() || c.is_whitespace() => format!("#\\x{:x}", c as u32),
+    None => format!("#\\{}", c),
+  }
+}
+
+fn is_delimiter(c: char) -> bool {
+  c.is_whitespace() || c == '(' || c == ')' || c == '{' || c == '}' || c == '"' || c == ';'
+}
+
+impl<'a> Iterator for Lexer<'a> {
+  type Item = Result<Lexeme, ParseError>;
+  fn next(&mut self) -> Option<Self::Item> {
+    if let Err(e) = self.skip_trivia() {
+      return Some(Err(e))
+    }
+    let (start, line, col) = (self.offset, self.line, self.col);
+    if self.starts_with("#;") {
+      self.bump();
+      self.bump();
+      return Some(Ok(Lexeme::DatumComment))
+    }
+    Some(match self.bump()? {
+      '\'' => Ok(Lexeme::Prefix("quote", self.span_from(start, line, col))),
+      '`' => Ok(Lexeme::Prefix("quasiquote", self.span_from(start, line, col))),
+      ',' if self.peek() == Some('@') => {
+        self.bump();
+        Ok(Lexeme::Prefix("unquote-splicing", self.span_from(start, line, col)))
+      },
+      ',' => Ok(Lexeme::Prefix("unquote", self.span_from(start, line, col))),
+      '(' => Ok(Lexeme::Open(self.span_from(start, line, col))),
+      ')' => Ok(Lexeme::Close(self.span_from(start, line, col))),
+      '{' => Ok(Lexeme::OpenBrace(Brace::Map, self.span_from(start, line, col))),
+      '#' if self.peek() == Some('{') => {
+        self.bump();
+        Ok(Lexeme::OpenBrace(Brace::Set, self.span_from(start, line, col)))
       },
-      s if s.is_whitespace() && curr.len() == 0 => (),
-      s if s.is_whitespace() => {
-        if buf.is_empty() {
-          done.push(Token::Word(curr.clone()));
-        } else {
-          let len = buf.len() - 1;
-          buf[len].add_next(Token::Word(curr.clone()));
+      '}' => Ok(Lexeme::CloseBrace(self.span_from(start, line, col))),
+      '"' => self.string(start, line, col),
+      // the character after #\ is part of the word even if it is a delimiter, so that #\( is
+      // a character
+      c => {
+        if c == '#' && self.peek() == Some('\\') {
+          self.bump();
+          self.bump();
         }
-        curr = String::from("");
+        while self.peek().map_or(false, |c| !is_delimiter(c)) {
+          self.bump();
+        }
+        let word = self.src[start..self.offset].to_string();
+        Ok(Lexeme::Atom(word, self.span_from(start, line, col)))
       },
-      x => curr.push(x),
+    })
+  }
+}
+
+// A datum being built up by the parser, either a group, a quoted datum or the top level.
+struct Frame {
+  items: Vec<Token>,
+  open: Option<Span>,
+  // the form which this frame's single datum will be wrapped in, for reader shorthand
+  prefix: Option<&'static str>,
+  // how many of the following datums have been commented out by `#;`
+  skip: usize,
+  // the collection the group is a literal of if it was opened by a brace, and so must be closed
+  // by one
+  brace: Option<Brace>,
+}
+
+impl Frame {
+  fn push(&mut self, t: Token) {
+    if self.skip > 0 {
+      self.skip -= 1;
+    } else {
+      self.items.push(t);
     }
-  };
-  if curr.len() > 0 {
-    // Should only happen in the case where the line is just a single token
-    done.push(Token::Word(curr.clone()));
   }
-  if buf.len() > 0 {
-    panic!("Unmatched left parens");
+}
+
+impl Frame {
+  fn new(open: Option<Span>, prefix: Option<&'static str>) -> Self {
+    Frame{items: Vec::new(), open, prefix, skip: 0, brace: None}
+  }
+}
+
+// Adds a completed datum to the innermost open frame, closing any reader shorthand it finishes.
+fn deliver(buf: &mut Vec<Frame>, done: &mut Frame, mut completed: Token) {
+  loop {
+    match buf.last_mut() {
+      Some(Frame{prefix: Some(prefix), open: Some(open), skip: 0, ..}) => {
+        let span = Span{end: completed.span().end, ..*open};
+        completed = Token::Group(vec!(Token::Word(prefix.to_string(), *open), completed), span);
+      },
+      Some(frame) => return frame.push(completed),
+      None => return done.push(completed),
+    }
+    buf.pop();
+  }
+}
+
+pub fn parse(body: String) -> Result<Vec<Token>, ParseError> {
+  let mut done = Frame::new(None, None);
+  // groups and quotes which have been opened but not closed yet
+  let mut buf: Vec<Frame> = Vec::new();
+  for lexeme in Lexer::new(&body) {
+    let lexeme = lexeme?;
+    let completed = match lexeme {
+      Lexeme::Open(span) => {
+        buf.push(Frame::new(Some(span), None));
+        continue
+      },
+      Lexeme::OpenBrace(brace, span) => {
+        buf.push(Frame{brace: Some(brace), ..Frame::new(Some(span), None)});
+        continue
+      },
+      Lexeme::Prefix(prefix, span) => {
+        buf.push(Frame::new(Some(span), Some(prefix)));
+        continue
+      },
+      Lexeme::DatumComment => {
+        buf.last_mut().unwrap_or(&mut done).skip += 1;
+        continue
+      },
+      Lexeme::Close(span) | Lexeme::CloseBrace(span) => {
+        let brace = if let Lexeme::CloseBrace(_) = lexeme { true } else { false };
+        let frame = match buf.pop() {
+          Some(frame) => frame,
+          None if brace => return Err(ParseError::MismatchedBrace(span)),
+          None => return Err(ParseError::ExtraRightParen(span)),
+        };
+        let open = frame.open.expect("Groups always have an opening paren");
+        if frame.prefix.is_some() {
+          return Err(ParseError::DanglingQuote(open))
+        }
+        let whole = Span{end: span.end, ..open};
+        match frame.brace {
+          Some(kind) if brace => Token::Braced(kind, frame.items, whole),
+          None if !brace => Token::Group(frame.items, whole),
+          _ => return Err(ParseError::MismatchedBrace(span)),
+        }
+      },
+      Lexeme::Atom(word, span) => Token::Word(word, span),
+      Lexeme::Str(s, span) => Token::Str(s, span),
+    };
+    deliver(&mut buf, &mut done, completed);
   }
   match buf.pop() {
-    None => (),
-    Some(group) => done.push(group),
+    None => Ok(done.items),
+    Some(Frame{prefix: Some(_), open, ..}) => Err(ParseError::DanglingQuote(open.unwrap())),
+    Some(frame) => Err(ParseError::UnmatchedLeftParen(frame.open.unwrap())),
+  }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Balance {
+  // Ready to be parsed, possibly reporting an error
+  Complete,
+  // Waiting on this many right parens
+  Open(usize),
+  // Inside of a string or block comment
+  Unterminated,
+}
+
+// Checks whether the source could be completed by more input, using the same lexer as `parse` so
+// that parens inside of strings and comments are not counted.
+pub fn balance(body: &str) -> Balance {
+  let mut depth = 0;
+  for lexeme in Lexer::new(body) {
+    match lexeme {
+      Ok(Lexeme::Open(_)) | Ok(Lexeme::OpenBrace(..)) => depth += 1,
+      Ok(Lexeme::Close(_)) | Ok(Lexeme::CloseBrace(_)) if depth == 0 => return Balance::Complete,
+      Ok(Lexeme::Close(_)) | Ok(Lexeme::CloseBrace(_)) => depth -= 1,
+      Ok(_) => (),
+      Err(ParseError::UnterminatedString(_)) | Err(ParseError::UnterminatedComment(_)) =>
+        return Balance::Unterminated,
+      Err(_) => return Balance::Complete,
+    }
   }
-  done
+  if depth == 0 { Balance::Complete } else { Balance::Open(depth) }
 }
 
 #[test]
@@ -70,3 +420,100 @@ fn test_parse() {
   let tokens = parse(String::from("(+ (+ 2 yes) 1)"));
   println!("{:?}", tokens);
 }
+
+#[test]
+fn test_parse_errors() {
+  let err = parse(String::from("(+ 1\n  2))")).unwrap_err();
+  assert_eq!(err, ParseError::ExtraRightParen(Span{start: 9, end: 10, line: 2, col: 5}));
+
+  let err = parse(String::from("(+ 1 (- 2 3)")).unwrap_err();
+  assert_eq!(err, ParseError::UnmatchedLeftParen(Span{start: 0, end: 1, line: 1, col: 1}));
+
+  let err = parse(String::from("{a 1)")).unwrap_err();
+  assert_eq!(err, ParseError::MismatchedBrace(Span{start: 4, end: 5, line: 1, col: 5}));
+  let err = parse(String::from("(#{a))")).unwrap_err();
+  assert_eq!(err, ParseError::MismatchedBrace(Span{start: 4, end: 5, line: 1, col: 5}));
+
+  let tokens = parse(String::from("x\n (f y)")).unwrap();
+  assert_eq!(tokens[1].span(), Span{start: 3, end: 8, line: 2, col: 2});
+}
+
+#[test]
+fn test_parse_strings() {
+  let tokens = parse(String::from(r#"(f "hello (world)" "a\tb\n\"c\\ \u{1F600}")"#)).unwrap();
+  match &tokens[0] {
+    Token::Group(g, _) => match (&g[1], &g[2]) {
+      (Token::Str(a, _), Token::Str(b, _)) => {
+        assert_eq!(a, "hello (world)");
+        assert_eq!(b, "a\tb\n\"c\\ \u{1F600}");
+      },
+      other => panic!("Expected strings, got {:?}", other),
+    },
+    other => panic!("Expected group, got {:?}", other),
+  }
+
+  let err = parse(String::from("(f \"abc)")).unwrap_err();
+  assert_eq!(err, ParseError::UnterminatedString(Span{start: 3, end: 8, line: 1, col: 4}));
+  let err = parse(String::from(r#""a\qb""#)).unwrap_err();
+  assert_eq!(err, ParseError::InvalidEscape(Span{start: 2, end: 4, line: 1, col: 3}));
+}
+
+#[test]
+fn test_parse_comments() {
+  let src = "; leading comment\n(f #| block #| nested |# ) |# 1 ; trailing\n #;(g 2) \";\")";
+  let tokens = parse(String::from(src)).unwrap();
+  assert_eq!(tokens.len(), 1);
+  match &tokens[0] {
+    Token::Group(g, _) => {
+      assert_eq!(g.len(), 3);
+      if let Token::Str(s, _) = &g[2] { assert_eq!(s, ";") } else { panic!("{:?}", g[2]) }
+    },
+    other => panic!("Expected group, got {:?}", other),
+  }
+
+  assert_eq!(balance("(f \"(\" ; )\n"), Balance::Open(1));
+  assert_eq!(balance("(f #| ) "), Balance::Unterminated);
+  assert_eq!(balance("(f))"), Balance::Complete);
+}
+
+#[test]
+fn test_parse_quotes() {
+  let tokens = parse(String::from("'(a `(b ,c ,@d))")).unwrap();
+  let expected = parse(String::from(
+    "(quote (a (quasiquote (b (unquote c) (unquote-splicing d)))))")).unwrap();
+  assert_eq!(format!("{:?}", strip(&tokens[0])), format!("{:?}", strip(&expected[0])));
+  assert_eq!(tokens[0].span(), Span{start: 0, end: 16, line: 1, col: 1});
+
+  let tokens = parse(String::from("'{a #{b ,c}}")).unwrap();
+  assert_eq!(strip(&tokens[0]), "(quote {a #{b (unquote c)}})");
+
+  let err = parse(String::from("(f ')")).unwrap_err();
+  assert_eq!(err, ParseError::DanglingQuote(Span{start: 3, end: 4, line: 1, col: 4}));
+
+  fn strip(t: &Token) -> String {
+    match t {
+      Token::Word(s, _) | Token::Str(s, _) => s.to_string(),
+      Token::Group(g, _) => format!("({})", g.iter().map(strip).collect::<Vec<_>>().join(" ")),
+      Token::Braced(brace, g, _) =>
+        format!("{}{}}}", brace.open(), g.iter().map(strip).collect::<Vec<_>>().join(" ")),
+    }
+  }
+}
+
+#[test]
+fn test_parse_chars() {
+  let tokens = parse(String::from("(#\\( #\\) #\\space #\\x3bb #\\x)")).unwrap();
+  match &tokens[0] {
+    Token::Group(g, _) => {
+      let chars: Vec<_> = g.iter().map(|t| match t {
+        Token::Word(s, _) => read_char(s),
+        other => panic!("Expected word, got {:?}", other),
+      }).collect();
+      assert_eq!(chars, vec!(Some('('), Some(')'), Some(' '), Some('\u{3bb}'), Some('x')));
+    },
+    other => panic!("Expected group, got {:?}", other),
+  }
+  assert_eq!(balance("(#\\("), Balance::Open(1));
+  assert_eq!(read_char("#\\bogus"), None);
+  assert_eq!(write_char('\u{7}'), "#\\x7");
+}
@@ -0,0 +1,230 @@
+use ast::{Expr, Type, GlobalEnv, List};
+use error::{Arity, RuntimeError};
+use number::Number;
+use default_env::{list, append};
+use std::sync::Arc;
+use std::borrow::Borrow;
+use std::cmp::Ordering;
+
+// The items of a list, in order
+pub(crate) fn items(v: &Arc<Type>) -> Result<Vec<Arc<Type>>, RuntimeError> {
+  let mut curr = match v.borrow() {
+    Type::List(l) => l,
+    _ => return Err(RuntimeError::mismatch("list", v)),
+  };
+  let mut items = Vec::new();
+  while let List::Cons(hd, tl) = curr.borrow() {
+    items.push(Arc::clone(hd));
+    curr = tl;
+  }
+  Ok(items)
+}
+
+// The items of a list, or the entries of a map as tuples or the items of a set in no particular
+// order, for the functions which go through each of them without depending on their order
+fn members(v: &Arc<Type>) -> Result<Vec<Arc<Type>>, RuntimeError> {
+  match v.borrow() {
+    Type::Map(m) => Ok(m.entries().into_iter()
+      .map(|(key, value)| Arc::new(Type::Tuple(key, value))).collect()),
+    Type::Set(s) => Ok(s.keys()),
+    _ => items(v).map_err(|_| RuntimeError::mismatch("list, map or set", v)),
+  }
+}
+
+pub(crate) fn index(v: &Arc<Type>) -> Result<usize, RuntimeError> {
+  match v.borrow() {
+    Type::Number(Number::Int(n)) if *n >= 0 => Ok(*n as usize),
+    _ => Err(RuntimeError::mismatch("index", v)),
+  }
+}
+
+fn number(v: &Arc<Type>) -> Result<&Number, RuntimeError> {
+  match v.borrow() {
+    Type::Number(n) => Ok(n),
+    _ => Err(RuntimeError::mismatch("number", v)),
+  }
+}
+
+fn truthy(v: &Arc<Type>) -> bool {
+  match v.borrow() {
+    Type::Bool(b) => *b,
+    _ => false,
+  }
+}
+
+// The order of two numbers or two strings, which are the only values sort can compare itself
+fn natural_order(a: &Arc<Type>, b: &Arc<Type>) -> Result<Ordering, RuntimeError> {
+  match (a.borrow(), b.borrow()) {
+    (Type::Number(x), Type::Number(y)) => Ok(x.partial_cmp(y).unwrap_or(Ordering::Equal)),
+    (Type::Str(x), Type::Str(y)) => Ok(x.cmp(y)),
+    (Type::Number(_), _) | (Type::Str(_), _) => Err(RuntimeError::mismatch(a.type_name(), b)),
+    _ => Err(RuntimeError::mismatch("number or string", a)),
+  }
+}
+
+// A stable merge sort by a comparison which may fail, as it can call back into the program
+fn merge_sort<F>(mut items: Vec<Arc<Type>>, less: &mut F) -> Result<Vec<Arc<Type>>, RuntimeError>
+  where F: FnMut(&Arc<Type>, &Arc<Type>) -> Result<bool, RuntimeError> {
+  if items.len() < 2 {
+    return Ok(items)
+  }
+  let right = items.split_off(items.len() / 2);
+  let (left, right) = (merge_sort(items, less)?, merge_sort(right, less)?);
+  let mut sorted = Vec::with_capacity(left.len() + right.len());
+  let (mut left, mut right) = (left.into_iter().peekable(), right.into_iter().peekable());
+  loop {
+    let take_right = match (left.peek(), right.peek()) {
+      (Some(l), Some(r)) => less(r, l)?,
+      (Some(_), None) => false,
+      (None, Some(_)) => true,
+      (None, None) => return Ok(sorted),
+    };
+    sorted.push(if take_right { right.next() } else { left.next() }.unwrap());
+  }
+}
+
+// Calls the function on each item of the list, appending together the lists it gives back
+pub fn append_map(x: Vec<Arc<Type>>, g_env: &mut GlobalEnv) -> Result<Arc<Type>, RuntimeError> {
+  Arity::Exactly(2).check(x.len())?;
+  let mut mapped = Vec::new();
+  for item in members(&x[1])? {
+    mapped.extend(items(&Expr::apply(&x[0], vec!(item), g_env)?)?);
+  }
+  Ok(Type::new_list(mapped))
+}
+
+// Finds the first pair in a list of pairs whose key is equal to the given one, where a pair is
+// either a tuple or a list whose first item is the key, or gives back f if there is none
+pub fn assoc(x: Vec<Arc<Type>>, _: &mut GlobalEnv) -> Result<Arc<Type>, RuntimeError> {
+  Arity::Exactly(2).check(x.len())?;
+  for pair in items(&x[1])? {
+    let key = match pair.borrow() {
+      Type::Tuple(k, _) => Arc::clone(k),
+      Type::List(l) => match l.borrow() {
+        List::Cons(k, _) => Arc::clone(k),
+        List::End => continue,
+      },
+      _ => return Err(RuntimeError::mismatch("pair", &pair)),
+    };
+    if key.equals(&x[0]) {
+      return Ok(pair)
+    }
+  }
+  Ok(Arc::new(Type::Bool(false)))
+}
+
+pub fn define(e: &mut GlobalEnv) {
+  e.define("list", Type::new_rust_closure(list));
+  e.define("append", Type::new_rust_closure(append));
+
+  e.define("length", Type::new_builtin(Arity::Exactly(1), |x, _| {
+    Ok(Type::new_number(members(&x[0])?.len() as i64))
+  }));
+
+  e.define("reverse", Type::new_builtin(Arity::Exactly(1), |x, _| {
+    Ok(Type::new_list(items(&x[0])?.into_iter().rev().collect()))
+  }));
+
+  // (map f l1 l2 ...) calls f with the nth item of each list, stopping at the shortest one
+  e.define("map", Type::new_builtin(Arity::AtLeast(2), |x, g_env| {
+    let lists = x[1..].iter().map(members).collect::<Result<Vec<_>, _>>()?;
+    let len = lists.iter().map(|l| l.len()).min().unwrap();
+    (0..len).map(|i| Expr::apply(&x[0], lists.iter().map(|l| Arc::clone(&l[i])).collect(), g_env))
+      .collect::<Result<_, _>>().map(Type::new_list)
+  }));
+
+  e.define("append-map", Type::new_builtin(Arity::Exactly(2), append_map));
+
+  e.define("filter", Type::new_builtin(Arity::Exactly(2), |x, g_env| {
+    let mut kept = Vec::new();
+    for item in members(&x[1])? {
+      if truthy(&Expr::apply(&x[0], vec!(Arc::clone(&item)), g_env)?) {
+        kept.push(item);
+      }
+    }
+    Ok(Type::new_list(kept))
+  }));
+
+  // (foldl f init l) calls (f acc item) from the first item, and foldr calls (f item acc) from
+  // the last
+  e.define("foldl", Type::new_builtin(Arity::Exactly(3), |x, g_env| {
+    members(&x[2])?.into_iter().fold(Ok(Arc::clone(&x[1])), |acc, item|
+      Expr::apply(&x[0], vec!(acc?, item), g_env))
+  }));
+  e.define("foldr", Type::new_builtin(Arity::Exactly(3), |x, g_env| {
+    members(&x[2])?.into_iter().rev().fold(Ok(Arc::clone(&x[1])), |acc, item|
+      Expr::apply(&x[0], vec!(item, acc?), g_env))
+  }));
+
+  // (range end), (range start end) or (range start end step), which excludes end
+  e.define("range", Type::new_builtin(Arity::Between(1, 3), |x, _| {
+    let ns = x.iter().map(number).collect::<Result<Vec<_>, _>>()?;
+    let zero = Number::Int(0);
+    let (start, end, step) = match ns.len() {
+      1 => (&zero, ns[0], &Number::Int(1)),
+      2 => (ns[0], ns[1], &Number::Int(1)),
+      _ => (ns[0], ns[1], ns[2]),
+    };
+    if step.is_zero() {
+      return Err(RuntimeError::mismatch("nonzero step", &x[2]))
+    }
+    let mut items = Vec::new();
+    let mut curr = start.clone();
+    while (*step > zero && curr < *end) || (*step < zero && curr > *end) {
+      let next = curr.add(step);
+      items.push(Type::new_number(curr));
+      curr = next;
+    }
+    Ok(Type::new_list(items))
+  }));
+
+  e.define("nth", Type::new_builtin(Arity::Exactly(2), |x, _| {
+    let (l, i) = (items(&x[0])?, index(&x[1])?);
+    l.get(i).cloned().ok_or(RuntimeError::IndexOutOfRange{index: i, length: l.len()})
+  }));
+
+  // take and drop stop at the end of the list rather than failing
+  e.define("take", Type::new_builtin(Arity::Exactly(2), |x, _| {
+    let (mut l, n) = (items(&x[0])?, index(&x[1])?);
+    l.truncate(n);
+    Ok(Type::new_list(l))
+  }));
+  e.define("drop", Type::new_builtin(Arity::Exactly(2), |x, _| {
+    let (l, n) = (items(&x[0])?, index(&x[1])?);
+    Ok(Type::new_list(l.into_iter().skip(n).collect()))
+  }));
+
+  // Pairs up the items of two lists as tuples, stopping at the shorter one
+  e.define("zip", Type::new_builtin(Arity::Exactly(2), |x, _| {
+    Ok(Type::new_list(items(&x[0])?.into_iter().zip(items(&x[1])?)
+      .map(|(a, b)| Arc::new(Type::Tuple(a, b))).collect()))
+  }));
+
+  e.define("any", Type::new_builtin(Arity::Exactly(2), |x, g_env| {
+    for item in members(&x[1])? {
+      if truthy(&Expr::apply(&x[0], vec!(item), g_env)?) {
+        return Ok(Arc::new(Type::Bool(true)))
+      }
+    }
+    Ok(Arc::new(Type::Bool(false)))
+  }));
+  e.define("all", Type::new_builtin(Arity::Exactly(2), |x, g_env| {
+    for item in members(&x[1])? {
+      if !truthy(&Expr::apply(&x[0], vec!(item), g_env)?) {
+        return Ok(Arc::new(Type::Bool(false)))
+      }
+    }
+    Ok(Arc::new(Type::Bool(true)))
+  }));
+
+  // (sort l) puts numbers or strings in increasing order, and (sort l less) orders by a function
+  // which is true when its first argument belongs before its second. Equal items keep their order.
+  e.define("sort", Type::new_builtin(Arity::Between(1, 2), |x, g_env| {
+    let l = items(&x[0])?;
+    match x.get(1) {
+      Some(f) => merge_sort(l, &mut |a, b|
+        Ok(truthy(&Expr::apply(f, vec!(Arc::clone(a), Arc::clone(b)), g_env)?))),
+      None => merge_sort(l, &mut |a, b| Ok(natural_order(a, b)? == Ordering::Less)),
+    }.map(Type::new_list)
+  }));
+}
@@ -0,0 +1,117 @@
+use ast::{Type, GlobalEnv};
+use error::{Arity, RuntimeError};
+use hamt::Map;
+use list;
+use std::sync::Arc;
+use std::borrow::Borrow;
+
+fn map_of(v: &Arc<Type>) -> Result<&Map, RuntimeError> {
+  match v.borrow() {
+    Type::Map(m) => Ok(m),
+    _ => Err(RuntimeError::mismatch("map", v)),
+  }
+}
+
+// The keys of a map or the items of a set, which are looked up in the same way
+fn keyed(v: &Arc<Type>) -> Result<&Map, RuntimeError> {
+  match v.borrow() {
+    Type::Map(m) | Type::Set(m) => Ok(m),
+    _ => Err(RuntimeError::mismatch("map or set", v)),
+  }
+}
+
+// Gives back a map or set of the same kind as the original
+fn rebuild(original: &Arc<Type>, m: Map) -> Arc<Type> {
+  Arc::new(match original.borrow() {
+    Type::Set(_) => Type::Set(m),
+    _ => Type::Map(m),
+  })
+}
+
+// Sets each key to the value after it, raising an error if the last key has no value
+fn insert_pairs(mut m: Map, pairs: &[Arc<Type>]) -> Result<Map, RuntimeError> {
+  if pairs.len() % 2 != 0 {
+    return Err(RuntimeError::Raised(Arc::new(Type::Error(
+      String::from("Expected a value for each key"), Arc::clone(&pairs[pairs.len() - 1])))))
+  }
+  for pair in pairs.chunks(2) {
+    m = m.insert(Arc::clone(&pair[0]), Arc::clone(&pair[1]));
+  }
+  Ok(m)
+}
+
+// The map with each key set to the value after it
+pub fn map_from(pairs: &[Arc<Type>]) -> Result<Map, RuntimeError> {
+  insert_pairs(Map::default(), pairs)
+}
+
+pub fn set_from(items: &[Arc<Type>]) -> Map {
+  items.iter().fold(Map::default(), |s, item| s.insert(Arc::clone(item), Type::unit()))
+}
+
+// (hash-map k v ...), which is what {k v ...} reads as
+pub fn hash_map(x: Vec<Arc<Type>>, _: &mut GlobalEnv) -> Result<Arc<Type>, RuntimeError> {
+  Ok(Arc::new(Type::Map(map_from(&x)?)))
+}
+
+// (hash-set x ...), which is what #{x ...} reads as
+pub fn hash_set(x: Vec<Arc<Type>>, _: &mut GlobalEnv) -> Result<Arc<Type>, RuntimeError> {
+  Ok(Arc::new(Type::Set(set_from(&x))))
+}
+
+pub fn define(e: &mut GlobalEnv) {
+  e.define("hash-map", Type::new_rust_closure(hash_map));
+  e.define("hash-set", Type::new_rust_closure(hash_set));
+
+  // (get m k) gives the value for k, or f if there is none, and (get m k default) gives default
+  // instead. Getting from a set gives back the item if it is in it.
+  e.define("get", Type::new_builtin(Arity::Between(2, 3), |x, _| {
+    let found = match x[0].borrow() {
+      Type::Map(m) => m.get(&x[1]).cloned(),
+      Type::Set(s) => s.get(&x[1]).map(|_| Arc::clone(&x[1])),
+      _ => return Err(RuntimeError::mismatch("map or set", &x[0])),
+    };
+    Ok(found.or_else(|| x.get(2).cloned()).unwrap_or_else(|| Arc::new(Type::Bool(false))))
+  }));
+
+  // (assoc m k v ...) sets each key of the map to the value after it, while (assoc k l) looks up
+  // k in the list of pairs l, where k can be a map too
+  e.define("assoc", Type::new_builtin(Arity::AtLeast(2), |x, g_env| {
+    match (x[0].borrow(), x[1].borrow()) {
+      (_, Type::List(_)) if x.len() == 2 => list::assoc(x, g_env),
+      (Type::Map(m), _) => Ok(Arc::new(Type::Map(insert_pairs(m.clone(), &x[1..])?))),
+      _ => list::assoc(x, g_env),
+    }
+  }));
+
+  // Removes each of the keys from a map, or the items from a set
+  e.define("dissoc", Type::new_builtin(Arity::AtLeast(1), |x, _| {
+    let m = x[1..].iter().fold(keyed(&x[0])?.clone(), |m, k| m.remove(k));
+    Ok(rebuild(&x[0], m))
+  }));
+
+  e.define("contains?", Type::new_builtin(Arity::Exactly(2), |x, _| {
+    Ok(Arc::new(Type::Bool(keyed(&x[0])?.get(&x[1]).is_some())))
+  }));
+
+  e.define("keys", Type::new_builtin(Arity::Exactly(1), |x, _| {
+    Ok(Type::new_list(keyed(&x[0])?.keys()))
+  }));
+  e.define("vals", Type::new_builtin(Arity::Exactly(1), |x, _| {
+    Ok(Type::new_list(map_of(&x[0])?.entries().into_iter().map(|(_, v)| v).collect()))
+  }));
+
+  // Merges maps, where a key in a later map takes its value from it, or takes the union of sets
+  e.define("merge", Type::new_builtin(Arity::AtLeast(1), |x, _| {
+    let mut merged = keyed(&x[0])?.clone();
+    for other in &x[1..] {
+      if other.type_name() != x[0].type_name() {
+        return Err(RuntimeError::mismatch(x[0].type_name(), other))
+      }
+      for (k, v) in keyed(other)?.entries() {
+        merged = merged.insert(k, v);
+      }
+    }
+    Ok(rebuild(&x[0], merged))
+  }));
+}
@@ -0,0 +1,296 @@
+use ast::{Type, GlobalEnv};
+use error::{Arity, RuntimeError};
+use number::Number;
+use num_rational::BigRational;
+use std::sync::Arc;
+use std::borrow::Borrow;
+
+fn number(v: &Arc<Type>) -> Result<&Number, RuntimeError> {
+  match v.borrow() {
+    Type::Number(n) => Ok(n),
+    _ => Err(RuntimeError::mismatch("number", v)),
+  }
+}
+
+fn numbers(x: &[Arc<Type>]) -> Result<Vec<&Number>, RuntimeError> {
+  x.iter().map(number).collect()
+}
+
+// A whole number, which may be a float with no fractional part
+fn integer(v: &Arc<Type>) -> Result<&Number, RuntimeError> {
+  match v.borrow() {
+    Type::Number(n) if n.is_integer() => Ok(n),
+    _ => Err(RuntimeError::mismatch("integer", v)),
+  }
+}
+
+fn nonzero(n: &Number) -> Result<&Number, RuntimeError> {
+  if n.is_zero() { Err(RuntimeError::DivideByZero) } else { Ok(n) }
+}
+
+// Subtracts or divides the rest of the numbers from the first, or from the identity if there is
+// only one, so that (- x) negates and (/ x) takes the reciprocal
+fn inverse_fold(x: &[Arc<Type>], identity: Number,
+  f: fn(&Number, &Number) -> Result<Number, RuntimeError>) -> Result<Arc<Type>, RuntimeError> {
+  let ns = numbers(x)?;
+  match ns.split_first() {
+    Some((first, [])) => f(&identity, first),
+    Some((first, rest)) => rest.iter().fold(Ok((*first).clone()), |acc, n| f(&acc?, n)),
+    None => unreachable!(),
+  }.map(Type::new_number)
+}
+
+// Whether each number is related to the next one
+fn chain(x: &[Arc<Type>], related: fn(&Number, &Number) -> bool)
+  -> Result<Arc<Type>, RuntimeError> {
+  let ns = numbers(x)?;
+  Ok(Arc::new(Type::Bool(ns.windows(2).all(|w| related(w[0], w[1])))))
+}
+
+// The number which is chosen over all the others, which is inexact if any of them are
+fn select(x: &[Arc<Type>], better: fn(&Number, &Number) -> bool)
+  -> Result<Arc<Type>, RuntimeError> {
+  let ns = numbers(x)?;
+  let best = ns.iter().skip(1).fold(ns[0], |best, n| if better(n, best) { n } else { best });
+  let exact = ns.iter().all(|n| n.is_exact());
+  Ok(Type::new_number(if exact { best.clone() } else { best.to_inexact() }))
+}
+
+// Applies an operation which is only defined on integers, to a divisor which must not be zero
+fn integral(x: &[Arc<Type>], f: fn(&Number, &Number) -> Option<Number>)
+  -> Result<Arc<Type>, RuntimeError> {
+  let (a, b) = (integer(&x[0])?, integer(&x[1])?);
+  Ok(Type::new_number(f(a, nonzero(b)?).unwrap()))
+}
+
+// Takes one or two arguments, which are passed as floats to the first or second function
+fn float_op(x: &[Arc<Type>], one: fn(f64) -> f64, two: fn(f64, f64) -> f64)
+  -> Result<Arc<Type>, RuntimeError> {
+  let ns = numbers(x)?;
+  Ok(Type::new_number(match ns.len() {
+    1 => one(ns[0].to_f64()),
+    _ => two(ns[0].to_f64(), ns[1].to_f64()),
+  }))
+}
+
+// Defines a builtin which applies the function to its one number
+macro_rules! unary {
+  ($e: expr, $name: expr, $f: expr) => {
+    $e.define($name, Type::new_builtin(Arity::Exactly(1), |x, _| {
+      Ok(Type::new_number($f(number(&x[0])?)))
+    }));
+  };
+}
+
+// Defines a builtin which applies the function to its one number as a float
+macro_rules! inexact {
+  ($e: expr, $name: expr, $f: expr) => {
+    unary!($e, $name, |n: &Number| $f(n.to_f64()));
+  };
+}
+
+// Defines a builtin which rounds a fraction in the same way with both rationals and floats
+macro_rules! rounding {
+  ($e: expr, $name: expr, $ratio: expr, $float: expr) => {
+    unary!($e, $name, |n: &Number| n.round_with($ratio, $float));
+  };
+}
+
+pub fn define(e: &mut GlobalEnv) {
+  e.define("+", Type::new_rust_closure(|x, _|
+    Ok(Type::new_number(numbers(&x)?.into_iter().fold(Number::Int(0), |acc, n| acc.add(n))))));
+  e.define("*", Type::new_rust_closure(|x, _|
+    Ok(Type::new_number(numbers(&x)?.into_iter().fold(Number::Int(1), |acc, n| acc.mul(n))))));
+  e.define("-", Type::new_builtin(Arity::AtLeast(1), |x, _|
+    inverse_fold(&x, Number::Int(0), |a, b| Ok(a.sub(b)))));
+  e.define("/", Type::new_builtin(Arity::AtLeast(1), |x, _|
+    inverse_fold(&x, Number::Int(1), |a, b| Ok(a.div(nonzero(b)?)))));
+
+  e.define("quotient", Type::new_builtin(Arity::Exactly(2), |x, _| integral(&x, Number::quotient)));
+  e.define("remainder", Type::new_builtin(Arity::Exactly(2), |x, _|
+    integral(&x, Number::remainder)));
+  e.define("modulo", Type::new_builtin(Arity::Exactly(2), |x, _| integral(&x, Number::modulo)));
+  // an exact power is only worked out while it stays below about a million bits, past which expt
+  // gives back the float it is nearest to instead, which may be infinite
+  e.define("expt", Type::new_builtin(Arity::Exactly(2), |x, _| {
+    let (a, b) = (number(&x[0])?, number(&x[1])?);
+    if a.is_exact() && a.is_zero() && b.is_exact() && *b < Number::Int(0) {
+      return Err(RuntimeError::DivideByZero)
+    }
+    Ok(Type::new_number(a.pow(b)))
+  }));
+
+  e.define("<", Type::new_builtin(Arity::AtLeast(1), |x, _| chain(&x, |a, b| a < b)));
+  e.define(">", Type::new_builtin(Arity::AtLeast(1), |x, _| chain(&x, |a, b| a > b)));
+  e.define("<=", Type::new_builtin(Arity::AtLeast(1), |x, _| chain(&x, |a, b| a <= b)));
+  e.define(">=", Type::new_builtin(Arity::AtLeast(1), |x, _| chain(&x, |a, b| a >= b)));
+  e.define("min", Type::new_builtin(Arity::AtLeast(1), |x, _| select(&x, |a, b| a < b)));
+  e.define("max", Type::new_builtin(Arity::AtLeast(1), |x, _| select(&x, |a, b| a > b)));
+
+  unary!(e, "abs", Number::abs);
+  unary!(e, "sqrt", Number::sqrt);
+  rounding!(e, "floor", BigRational::floor, f64::floor);
+  rounding!(e, "ceiling", BigRational::ceil, f64::ceil);
+  rounding!(e, "round", BigRational::round, f64::round);
+  rounding!(e, "truncate", BigRational::trunc, f64::trunc);
+  inexact!(e, "exp", f64::exp);
+  inexact!(e, "sin", f64::sin);
+  inexact!(e, "cos", f64::cos);
+  inexact!(e, "tan", f64::tan);
+  inexact!(e, "asin", f64::asin);
+  inexact!(e, "acos", f64::acos);
+  // (log x) is the natural log, and (log x b) the log in base b
+  e.define("log", Type::new_builtin(Arity::Between(1, 2), |x, _| float_op(&x, f64::ln, f64::log)));
+  // (atan y x) gives the angle of the point (x, y)
+  e.define("atan", Type::new_builtin(Arity::Between(1, 2), |x, _|
+    float_op(&x, f64::atan, f64::atan2)));
+
+  unary!(e, "exact->inexact", Number::to_inexact);
+  e.define("inexact->exact", Type::new_builtin(Arity::Exactly(1), |x, _| {
+    number(&x[0])?.to_exact().map(Type::new_number)
+      .ok_or_else(|| RuntimeError::mismatch("finite number", &x[0]))
+  }));
+  e.define("exact?", Type::new_builtin(Arity::Exactly(1), |x, _| {
+    Ok(Arc::new(Type::Bool(number(&x[0])?.is_exact())))
+  }));
+  e.define("inexact?", Type::new_builtin(Arity::Exactly(1), |x, _| {
+    Ok(Arc::new(Type::Bool(!number(&x[0])?.is_exact())))
+  }));
+}
+
+#[cfg(test)]
+mod tests {
+  use ast::{Env, Expr, Type};
+  use error::RuntimeError;
+  use number::Number;
+  use quickcheck::{quickcheck, TestResult};
+  use std::borrow::Borrow;
+
+  fn apply(name: &str, args: Vec<Number>) -> Result<Number, RuntimeError> {
+    let mut g_env = Env::default_global();
+    let func = g_env.get(name).unwrap();
+    let v = Expr::apply(&func, args.into_iter().map(Type::new_number).collect(), &mut g_env)?;
+    match v.borrow() {
+      Type::Number(n) => Ok(n.clone()),
+      _ => panic!("{} gave back {:?}", name, v),
+    }
+  }
+
+  fn floats(name: &str, args: &[f64]) -> f64 {
+    match apply(name, args.iter().map(|n| Number::Float(*n)).collect()).unwrap() {
+      Number::Float(n) => n,
+      n => panic!("{} gave back the exact number {}", name, n),
+    }
+  }
+
+  fn same(a: f64, b: f64) -> bool {
+    a == b || (a.is_nan() && b.is_nan())
+  }
+
+  // Spreads small generated integers across the whole range of i64s, so that they overflow
+  fn spread(n: i64, shift: u8) -> i64 {
+    n.wrapping_shl(u32::from(shift % 64))
+  }
+
+  #[test]
+  fn exact_arithmetic_matches_i128() {
+    fn prop(a: i64, b: i64, c: i64, shift: u8) -> bool {
+      let (a, b) = (spread(a, shift), spread(b, shift / 2));
+      let (x, y, z) = (i128::from(a), i128::from(b), i128::from(c));
+      let check = |name, args: Vec<i64>, expected: i128|
+        apply(name, args.into_iter().map(Number::Int).collect()).unwrap().to_string() ==
+          expected.to_string();
+      check("+", vec!(a, b, c), x + y + z) && check("-", vec!(a, b), x - y) &&
+        check("-", vec!(a), -x) && check("*", vec!(a, b, c), x * y * z) &&
+        check("+", vec!(), 0) && check("*", vec!(), 1) && check("abs", vec!(a), x.abs())
+    }
+    quickcheck(prop as fn(i64, i64, i64, u8) -> bool);
+  }
+
+  #[test]
+  fn division_is_exact() {
+    fn prop(a: i64, b: i64, shift: u8) -> TestResult {
+      let (a, b) = (spread(a, shift), b);
+      if b == 0 {
+        let errors = ["/", "quotient", "remainder", "modulo"].iter().all(|f|
+          match apply(f, vec!(Number::Int(a), Number::Int(b))) {
+            Err(RuntimeError::DivideByZero) => true,
+            _ => false,
+          });
+        return TestResult::from_bool(errors)
+      }
+      let (x, y) = (i128::from(a), i128::from(b));
+      let int = |name, expected: i128| apply(name, vec!(Number::Int(a), Number::Int(b))).unwrap()
+        .to_string() == expected.to_string();
+      let ratio = apply("/", vec!(Number::Int(a), Number::Int(b))).unwrap();
+      let m = x % y;
+      TestResult::from_bool(ratio.mul(&Number::Int(b)) == Number::Int(a) &&
+        ratio.is_integer() == (x % y == 0) && int("quotient", x / y) && int("remainder", x % y) &&
+        int("modulo", if m != 0 && (m < 0) != (y < 0) { m + y } else { m }))
+    }
+    quickcheck(prop as fn(i64, i64, u8) -> TestResult);
+  }
+
+  #[test]
+  fn floats_match_f64() {
+    fn prop(a: f64, b: f64, c: f64) -> bool {
+      same(floats("+", &[a, b, c]), a + b + c) && same(floats("-", &[a, b]), a - b) &&
+        same(floats("-", &[a]), -a) && same(floats("*", &[a, b]), a * b) &&
+        (b == 0.0 || same(floats("/", &[a, b]), a / b)) &&
+        same(floats("expt", &[a, b]), a.powf(b)) && same(floats("atan", &[a, b]), a.atan2(b)) &&
+        same(floats("log", &[a, b]), a.log(b))
+    }
+    quickcheck(prop as fn(f64, f64, f64) -> bool);
+  }
+
+  #[test]
+  fn functions_match_f64() {
+    fn prop(a: f64) -> bool {
+      let functions: Vec<(&str, fn(f64) -> f64)> = vec!(("abs", f64::abs), ("floor", f64::floor),
+        ("ceiling", f64::ceil), ("round", f64::round), ("truncate", f64::trunc),
+        ("sqrt", f64::sqrt), ("exp", f64::exp), ("log", f64::ln), ("sin", f64::sin),
+        ("cos", f64::cos), ("tan", f64::tan), ("asin", f64::asin), ("acos", f64::acos),
+        ("atan", f64::atan));
+      functions.into_iter().all(|(name, f)| same(floats(name, &[a]), f(a)))
+    }
+    quickcheck(prop as fn(f64) -> bool);
+  }
+
+  #[test]
+  fn comparisons_match() {
+    fn prop(a: i64, b: f64, c: i64) -> bool {
+      let cmp = |name, f: fn(f64, f64) -> bool| {
+        let mut g_env = Env::default_global();
+        let func = g_env.get(name).unwrap();
+        let args = vec!(Type::new_number(a), Type::new_number(b), Type::new_number(c));
+        let expected = f(a as f64, b) && f(b, c as f64);
+        Expr::apply(&func, args, &mut g_env).unwrap().equals(&Type::Bool(expected))
+      };
+      cmp("<", |x, y| x < y) && cmp(">", |x, y| x > y) && cmp("<=", |x, y| x <= y) &&
+        cmp(">=", |x, y| x >= y) &&
+        apply("min", vec!(Number::Int(a), Number::Int(c))).unwrap() == Number::Int(a.min(c)) &&
+        apply("max", vec!(Number::Int(a), Number::Int(c))).unwrap() == Number::Int(a.max(c))
+    }
+    quickcheck(prop as fn(i64, f64, i64) -> bool);
+  }
+
+  #[test]
+  fn printed_numbers_read_back() {
+    fn prop(a: i64, b: i64, f: f64, shift: u8) -> bool {
+      let ratio = Number::Int(spread(a, shift)).mul(&Number::Int(a)).div(&Number::Int(b.max(1)));
+      [ratio, Number::Float(f)].iter().all(|n| {
+        let read = Number::parse(&n.to_string()).unwrap();
+        read.is_exact() == n.is_exact() && (read == *n || f.is_nan())
+      })
+    }
+    quickcheck(prop as fn(i64, i64, f64, u8) -> bool);
+  }
+
+  #[test]
+  fn large_powers_are_floats() {
+    let int = |n: i64| Number::Int(n);
+    assert!(apply("expt", vec!(int(2), int(1 << 19))).unwrap().is_exact());
+    assert_eq!(apply("expt", vec!(int(3), int(1 << 30))).unwrap().to_string(), "+inf.0");
+    assert_eq!(apply("expt", vec!(int(-1), int(1 << 30))).unwrap(), int(1));
+  }
+}
@@ -0,0 +1,343 @@
+use num_bigint::BigInt;
+use num_rational::BigRational;
+use num_traits::{Signed, ToPrimitive, Num, pow};
+use num_integer::Integer;
+use std::cmp::Ordering;
+use std::hash::{Hash, Hasher};
+use std::fmt;
+
+// The most bits an exact power is worked out to, past which it is given as a float instead
+const MAX_POW_BITS: u64 = 1 << 20;
+
+// A number, which is exact unless it is a float. Exact numbers are always kept in the simplest
+// form that holds them, so an integer which fits in an i64 is an Int, and a Ratio is never a
+// whole number.
+#[derive(Clone)]
+pub enum Number {
+  Int(i64),
+  Big(BigInt),
+  Ratio(BigRational),
+  Float(f64),
+}
+
+impl From<i64> for Number {
+  fn from(n: i64) -> Number {
+    Number::Int(n)
+  }
+}
+
+impl From<f64> for Number {
+  fn from(n: f64) -> Number {
+    Number::Float(n)
+  }
+}
+
+// Reads an integer in the given base, with an optional sign
+fn integer(s: &str, radix: u32) -> Option<Number> {
+  let digits = if s.starts_with('+') || s.starts_with('-') { &s[1..] } else { s };
+  if digits.is_empty() || !digits.chars().all(|c| c.is_digit(radix)) {
+    return None
+  }
+  match i64::from_str_radix(s, radix) {
+    Ok(n) => Some(Number::Int(n)),
+    Err(_) => BigInt::from_str_radix(digits, radix).ok()
+      .map(|n| Number::big(if s.starts_with('-') { -n } else { n })),
+  }
+}
+
+impl Number {
+  pub fn big(n: BigInt) -> Number {
+    match n.to_i64() {
+      Some(n) => Number::Int(n),
+      None => Number::Big(n),
+    }
+  }
+  pub fn ratio(r: BigRational) -> Number {
+    if r.is_integer() { Number::big(r.to_integer()) } else { Number::Ratio(r) }
+  }
+  // Reads a number literal: an integer, a rational like 1/3, a float like 1.5 or 1e10, or an
+  // integer in another base like #x1F, #o17 or #b101
+  pub fn parse(s: &str) -> Option<Number> {
+    let radix = match s.get(..2) {
+      Some("#x") | Some("#X") => 16,
+      Some("#o") | Some("#O") => 8,
+      Some("#b") | Some("#B") => 2,
+      Some("#d") | Some("#D") => 10,
+      _ => 0,
+    };
+    if radix != 0 {
+      return integer(&s[2..], radix)
+    }
+    match s {
+      "+inf.0" => return Some(Number::Float(::std::f64::INFINITY)),
+      "-inf.0" => return Some(Number::Float(::std::f64::NEG_INFINITY)),
+      "+nan.0" | "-nan.0" => return Some(Number::Float(::std::f64::NAN)),
+      _ => (),
+    }
+    // a number has to start with a digit, after any sign or point, so that words like inf are
+    // left as symbols
+    let unsigned = if s.starts_with('+') || s.starts_with('-') { &s[1..] } else { s };
+    let unpointed = if unsigned.starts_with('.') { &unsigned[1..] } else { unsigned };
+    if !unpointed.chars().next().map_or(false, |c| c.is_digit(10)) {
+      return None
+    }
+    match s.find('/') {
+      Some(i) if s[i + 1..].chars().all(|c| c.is_digit(10)) =>
+        match (integer(&s[..i], 10)?, integer(&s[i + 1..], 10)?) {
+          (_, Number::Int(0)) => None,
+          (n, d) => Some(Number::ratio(BigRational::new(n.to_ratio()?.to_integer(),
+            d.to_ratio()?.to_integer()))),
+        },
+      Some(_) => None,
+      None => integer(s, 10).or_else(|| s.parse::<f64>().ok().map(Number::Float)),
+    }
+  }
+  pub fn is_exact(&self) -> bool {
+    match self {
+      Number::Float(_) => false,
+      _ => true,
+    }
+  }
+  pub fn is_zero(&self) -> bool {
+    match self {
+      Number::Int(n) => *n == 0,
+      Number::Float(n) => *n == 0.0,
+      _ => false,
+    }
+  }
+  pub fn is_integer(&self) -> bool {
+    match self {
+      Number::Int(_) | Number::Big(_) => true,
+      Number::Ratio(_) => false,
+      Number::Float(n) => n.fract() == 0.0,
+    }
+  }
+  // The exact value of the number, which is None for a float
+  pub fn to_ratio(&self) -> Option<BigRational> {
+    match self {
+      Number::Int(n) => Some(BigRational::from_integer(BigInt::from(*n))),
+      Number::Big(n) => Some(BigRational::from_integer(n.clone())),
+      Number::Ratio(r) => Some(r.clone()),
+      Number::Float(_) => None,
+    }
+  }
+  pub fn to_f64(&self) -> f64 {
+    let big = |n: &BigInt| n.to_f64().unwrap_or_else(|| if n.is_negative() {
+      ::std::f64::NEG_INFINITY
+    } else {
+      ::std::f64::INFINITY
+    });
+    match self {
+      Number::Int(n) => *n as f64,
+      Number::Big(n) => big(n),
+      Number::Ratio(r) => {
+        // both parts are shifted down until they fit in a float, which keeps their ratio
+        let bits = r.numer().bits().max(r.denom().bits());
+        let shift = if bits > 1000 { bits - 1000 } else { 0 };
+        big(&(r.numer() >> shift)) / big(&(r.denom() >> shift))
+      },
+      Number::Float(n) => *n,
+    }
+  }
+  pub fn to_inexact(&self) -> Number {
+    Number::Float(self.to_f64())
+  }
+  // The exact value of a float, which is None for infinities and NaN
+  pub fn to_exact(&self) -> Option<Number> {
+    match self {
+      Number::Float(n) => BigRational::from_float(*n).map(Number::ratio),
+      n => Some(n.clone()),
+    }
+  }
+  // Applies the operation to both numbers as floats if either is one, and otherwise exactly,
+  // trying i64s first before falling back to rationals
+  fn combine(&self, o: &Number, int: fn(i64, i64) -> Option<i64>,
+    ratio: fn(BigRational, BigRational) -> BigRational, float: fn(f64, f64) -> f64) -> Number {
+    match (self, o) {
+      (Number::Int(a), Number::Int(b)) if int(*a, *b).is_some() =>
+        Number::Int(int(*a, *b).unwrap()),
+      (Number::Float(_), _) | (_, Number::Float(_)) =>
+        Number::Float(float(self.to_f64(), o.to_f64())),
+      _ => Number::ratio(ratio(self.to_ratio().unwrap(), o.to_ratio().unwrap())),
+    }
+  }
+  pub fn add(&self, o: &Number) -> Number {
+    self.combine(o, i64::checked_add, |a, b| a + b, |a, b| a + b)
+  }
+  pub fn sub(&self, o: &Number) -> Number {
+    self.combine(o, i64::checked_sub, |a, b| a - b, |a, b| a - b)
+  }
+  pub fn mul(&self, o: &Number) -> Number {
+    self.combine(o, i64::checked_mul, |a, b| a * b, |a, b| a * b)
+  }
+  // Divides by a number, which must not be zero
+  pub fn div(&self, o: &Number) -> Number {
+    let int = |a: i64, b| if a.checked_rem(b) == Some(0) { a.checked_div(b) } else { None };
+    self.combine(o, int, |a, b| a / b, |a, b| a / b)
+  }
+  // Applies an operation defined on integers, which is None if either number is a fraction
+  fn integral(&self, o: &Number, int: fn(i64, i64) -> Option<i64>,
+    big: fn(&BigInt, &BigInt) -> BigInt, float: fn(f64, f64) -> f64) -> Option<Number> {
+    Some(match (self, o) {
+      (Number::Ratio(_), _) | (_, Number::Ratio(_)) => return None,
+      (Number::Int(a), Number::Int(b)) if int(*a, *b).is_some() =>
+        Number::Int(int(*a, *b).unwrap()),
+      (Number::Float(_), _) | (_, Number::Float(_)) =>
+        Number::Float(float(self.to_f64(), o.to_f64())),
+      _ => Number::big(big(&self.to_ratio()?.to_integer(), &o.to_ratio()?.to_integer())),
+    })
+  }
+  // The quotient rounded towards zero, of a divisor which must not be zero
+  pub fn quotient(&self, o: &Number) -> Option<Number> {
+    self.integral(o, i64::checked_div, |a, b| a / b, |a, b| (a / b).trunc())
+  }
+  // The remainder with the sign of the dividend
+  pub fn remainder(&self, o: &Number) -> Option<Number> {
+    self.integral(o, i64::checked_rem, |a, b| a % b, |a, b| a % b)
+  }
+  // The remainder with the sign of the divisor
+  pub fn modulo(&self, o: &Number) -> Option<Number> {
+    let int = |a: i64, b| a.checked_rem(b).map(|r|
+      if r != 0 && (r < 0) != (b < 0) { r + b } else { r });
+    let float = |a: f64, b| {
+      let r = a % b;
+      if r != 0.0 && (r < 0.0) != (b < 0.0) { r + b } else { r }
+    };
+    self.integral(o, int, |a, b| a.mod_floor(b), float)
+  }
+  pub fn neg(&self) -> Number {
+    Number::Int(0).sub(self)
+  }
+  pub fn abs(&self) -> Number {
+    if *self < Number::Int(0) { self.neg() } else { self.clone() }
+  }
+  // Rounds a fraction to a whole number, leaving integers as they are
+  pub fn round_with(&self, ratio: fn(&BigRational) -> BigRational, float: fn(f64) -> f64)
+    -> Number {
+    match self {
+      Number::Ratio(r) => Number::ratio(ratio(r)),
+      Number::Float(n) => Number::Float(float(*n)),
+      n => n.clone(),
+    }
+  }
+  // The square root, which is exact for the squares of integers
+  pub fn sqrt(&self) -> Number {
+    match self {
+      Number::Int(_) | Number::Big(_) if *self >= Number::Int(0) => {
+        let n = self.to_ratio().unwrap().to_integer();
+        let root = n.sqrt();
+        if &root * &root == n { Number::big(root) } else { Number::Float(self.to_f64().sqrt()) }
+      },
+      n => Number::Float(n.to_f64().sqrt()),
+    }
+  }
+  // Raises the number to a power, which is exact for an exact number raised to an integer unless
+  // the result would be too big to work out. An exact zero must not be raised to a negative power.
+  pub fn pow(&self, e: &Number) -> Number {
+    match (self, e) {
+      (Number::Int(a), Number::Int(b)) if *b >= 0 && *b <= u32::max_value() as i64 &&
+        a.checked_pow(*b as u32).is_some() => Number::Int(a.checked_pow(*b as u32).unwrap()),
+      (Number::Float(_), _) => Number::Float(self.to_f64().powf(e.to_f64())),
+      (_, Number::Int(b)) if *b >= i32::min_value() as i64 && *b <= i32::max_value() as i64 &&
+        self.pow_bits(b.abs() as u64) <= MAX_POW_BITS =>
+      {
+        let r = self.to_ratio().unwrap();
+        let e = b.abs() as usize;
+        let r = BigRational::new(pow(r.numer().clone(), e), pow(r.denom().clone(), e));
+        Number::ratio(if *b < 0 { r.recip() } else { r })
+      },
+      _ => Number::Float(self.to_f64().powf(e.to_f64())),
+    }
+  }
+  // About how many bits the numerator or denominator of an exact number raised to the power has.
+  // Powers of 0, 1 and -1 take no bits, as they stay the same size.
+  fn pow_bits(&self, e: u64) -> u64 {
+    let bits = |n: &BigInt| (n.bits() as u64).saturating_sub(1);
+    match self.to_ratio() {
+      Some(r) => bits(r.numer()).max(bits(r.denom())).saturating_mul(e),
+      None => u64::max_value(),
+    }
+  }
+}
+
+impl PartialEq for Number {
+  fn eq(&self, o: &Number) -> bool {
+    self.partial_cmp(o) == Some(Ordering::Equal)
+  }
+}
+
+// Numbers which are equal hash the same even if one of them is a float, as they are hashed by
+// their exact value
+impl Hash for Number {
+  fn hash<H: Hasher>(&self, h: &mut H) {
+    match self.to_exact() {
+      Some(Number::Int(n)) => n.hash(h),
+      Some(Number::Big(n)) => n.hash(h),
+      Some(Number::Ratio(n)) => n.hash(h),
+      // infinities and NaN, which are rarely keys
+      _ => (),
+    }
+  }
+}
+
+// Numbers are compared by value, whether or not they are exact. An exact number is compared with
+// a float by the float's exact value, as converting it to a float could round it to be equal.
+impl PartialOrd for Number {
+  fn partial_cmp(&self, o: &Number) -> Option<Ordering> {
+    match (self, o) {
+      (Number::Int(a), Number::Int(b)) => a.partial_cmp(b),
+      (Number::Float(a), Number::Float(b)) => a.partial_cmp(b),
+      // every int up to 2^53 is a float as well
+      (Number::Int(a), Number::Float(b)) if a.abs() <= 1 << 53 => (*a as f64).partial_cmp(b),
+      (Number::Float(a), Number::Int(b)) if b.abs() <= 1 << 53 => a.partial_cmp(&(*b as f64)),
+      // an infinity is past every exact number, which is finite
+      (Number::Float(a), _) if !a.is_finite() => a.partial_cmp(&0.0),
+      (_, Number::Float(b)) if !b.is_finite() => 0.0.partial_cmp(b),
+      _ => self.to_exact()?.to_ratio().partial_cmp(&o.to_exact()?.to_ratio()),
+    }
+  }
+}
+
+impl fmt::Display for Number {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      Number::Int(n) => write!(f, "{}", n),
+      Number::Big(n) => write!(f, "{}", n),
+      Number::Ratio(r) => write!(f, "{}/{}", r.numer(), r.denom()),
+      Number::Float(n) if n.is_nan() => write!(f, "+nan.0"),
+      Number::Float(n) if n.is_infinite() => write!(f, "{}inf.0", if *n > 0.0 { "+" } else { "-" }),
+      // whole floats keep their point, so that they read back as floats
+      Number::Float(n) if n.fract() == 0.0 => write!(f, "{:.1}", n),
+      Number::Float(n) => write!(f, "{}", n),
+    }
+  }
+}
+
+impl fmt::Debug for Number {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "{}", self)
+  }
+}
+
+#[test]
+fn test_parse() {
+  let cases = vec!(("42", "42"), ("-7", "-7"), ("+3", "3"), ("2/4", "1/2"), ("-6/3", "-2"),
+    ("#x1F", "31"), ("#b-101", "-5"), ("#o17", "15"), ("1e10", "10000000000.0"), ("1.5", "1.5"),
+    (".5", "0.5"), ("-inf.0", "-inf.0"), ("100000000000000000000", "100000000000000000000"));
+  for (src, expected) in cases {
+    assert_eq!(Number::parse(src).map(|n| n.to_string()), Some(expected.to_string()), "{}", src);
+  }
+  for src in &["inf", "nan", "-", "1/0", "1/-2", "#x", "1.5/2", "a1", "1a", "..5"] {
+    assert!(Number::parse(src).is_none(), "{} should not be a number", src);
+  }
+}
+
+#[test]
+fn test_pow() {
+  let cases = vec!((2, 10, "1024"), (-3, 3, "-27"), (2, -2, "1/4"), (1, 2000000000, "1"),
+    (-1, 2000000001, "-1"), (0, 2000000000, "0"), (2, 64, "18446744073709551616"),
+    (3, 2000000000, "+inf.0"), (-2, 2000000001, "-inf.0"), (3, -2000000000, "0.0"));
+  for (a, b, expected) in cases {
+    assert_eq!(Number::Int(a).pow(&Number::Int(b)).to_string(), expected, "{} {}", a, b);
+  }
+}
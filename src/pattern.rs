@@ -0,0 +1,162 @@
+use lisp_parse::Token;
+use to_ast::{SyntaxError, literal};
+use ast::{Expr, Type, Assign, Defn, RustClosureFn};
+use default_env::{hd, tl, fst, snd, is_cons, is_tuple, equal, no_match};
+use std::sync::Arc;
+
+// A shape which a value can be matched against, binding names to the parts of it
+#[derive(Debug)]
+pub enum Pattern {
+  Wildcard,
+  Bind(String),
+  Literal(Arc<Type>),
+  // A list with at least one item, matching its head and its tail
+  Cons(Box<Pattern>, Box<Pattern>),
+  // (tuple a b) or (pair a b), matching each part of a tuple
+  Tuple(Box<Pattern>, Box<Pattern>),
+}
+
+fn is_dot(t: &Token) -> bool {
+  match t {
+    Token::Word(s, _) => s == ".",
+    _ => false,
+  }
+}
+
+// (a b) matches a list of exactly two items, and (a b . rest) a list of at least two, with rest
+// matching the rest of it
+fn list(t: &Token, g: &[Token]) -> Result<Pattern, SyntaxError> {
+  let (items, rest) = match g.iter().position(is_dot) {
+    Some(i) if i > 0 && i == g.len() - 2 => (&g[..i], Pattern::parse(&g[i + 1])?),
+    Some(_) => return SyntaxError::at(t, "Expected one pattern after the ."),
+    None => (g, Pattern::Literal(Type::new_empty_list())),
+  };
+  items.iter().rev().fold(Ok(rest), |rest, p|
+    Ok(Pattern::Cons(Box::new(Pattern::parse(p)?), Box::new(rest?))))
+}
+
+fn call(f: RustClosureFn, args: Vec<Expr>) -> Expr {
+  Expr::Call(Arc::new(Expr::Value(Type::new_rust_closure(f))),
+    args.into_iter().map(Arc::new).collect())
+}
+
+impl Pattern {
+  pub fn parse(t: &Token) -> Result<Pattern, SyntaxError> {
+    match t {
+      Token::Word(s, _) if s == "_" => Ok(Pattern::Wildcard),
+      Token::Word(s, _) if s == "." => SyntaxError::at(t, "Unexpected ."),
+      Token::Word(s, _) => Ok(literal(t, s)?.map_or_else(|| Pattern::Bind(s.to_string()),
+        Pattern::Literal)),
+      Token::Str(..) => Ok(Pattern::Literal(t.to_datum()?)),
+      Token::Braced(..) => SyntaxError::at(t, "Maps and sets can only be matched when quoted"),
+      Token::Group(g, _) => match g.get(0) {
+        None => Ok(Pattern::Literal(Type::unit())),
+        Some(Token::Word(s, _)) if s == "quote" && g.len() == 2 =>
+          Ok(Pattern::Literal(g[1].to_datum()?)),
+        Some(Token::Word(s, _)) if s == "tuple" || s == "pair" => match g.len() {
+          3 => Ok(Pattern::Tuple(Box::new(Pattern::parse(&g[1])?),
+            Box::new(Pattern::parse(&g[2])?))),
+          _ => SyntaxError::at(t, "A tuple pattern must have two parts"),
+        },
+        _ => list(t, g),
+      },
+    }
+  }
+  // Whether every value the other pattern matches is also matched by this one
+  pub fn covers(&self, other: &Pattern) -> bool {
+    match (self, other) {
+      (Pattern::Wildcard, _) | (Pattern::Bind(_), _) => true,
+      (Pattern::Literal(a), Pattern::Literal(b)) => a.equals(b),
+      (Pattern::Cons(h1, t1), Pattern::Cons(h2, t2)) |
+      (Pattern::Tuple(h1, t1), Pattern::Tuple(h2, t2)) => h1.covers(h2) && t1.covers(t2),
+      _ => false,
+    }
+  }
+  // Adds the checks which the value found at the path must pass, outermost first
+  fn tests(&self, path: &Expr, out: &mut Vec<Expr>) {
+    let (check, first, second, a, b): (RustClosureFn, RustClosureFn, RustClosureFn, _, _) =
+      match self {
+        Pattern::Wildcard | Pattern::Bind(_) => return,
+        Pattern::Literal(v) =>
+          return out.push(call(equal, vec!(path.clone(), Expr::Value(Arc::clone(v))))),
+        Pattern::Cons(h, t) => (is_cons, hd, tl, h, t),
+        Pattern::Tuple(l, r) => (is_tuple, fst, snd, l, r),
+      };
+    out.push(call(check, vec!(path.clone())));
+    a.tests(&call(first, vec!(path.clone())), out);
+    b.tests(&call(second, vec!(path.clone())), out);
+  }
+  // Adds each name the pattern binds along with how to get its value from the path
+  fn bindings(&self, path: &Expr, out: &mut Vec<(String, Arc<Expr>)>) {
+    let (first, second, a, b): (RustClosureFn, RustClosureFn, _, _) = match self {
+      Pattern::Wildcard | Pattern::Literal(_) => return,
+      Pattern::Bind(name) => return out.push((name.to_string(), Arc::new(path.clone()))),
+      Pattern::Cons(h, t) => (hd, tl, h, t),
+      Pattern::Tuple(l, r) => (fst, snd, l, r),
+    };
+    a.bindings(&call(first, vec!(path.clone())), out);
+    b.bindings(&call(second, vec!(path.clone())), out);
+  }
+}
+
+// One arm of a match, whose body is evaluated if the pattern matches and then the guard is true
+pub struct Arm {
+  pub pattern: Pattern,
+  pub guard: Option<Arc<Expr>>,
+  pub body: Arc<Expr>,
+}
+
+impl Arm {
+  // Evaluates to the body if the value at the path matches, and otherwise to next
+  fn lower(self, path: &Expr, next: Expr) -> Expr {
+    let mut tests = Vec::new();
+    self.pattern.tests(path, &mut tests);
+    let mut bindings = Vec::new();
+    self.pattern.bindings(path, &mut bindings);
+    let test = tests.into_iter().rev().fold(None, |rest, test| Some(match rest {
+      Some(rest) => Expr::If(Arc::new(test), Arc::new(rest),
+        Arc::new(Expr::Value(Arc::new(Type::Bool(false))))),
+      None => test,
+    }));
+    let bind = |body: Expr| match bindings.len() {
+      0 => body,
+      _ => Expr::Assign(Assign::Locals(bindings.clone(), Arc::new(body))),
+    };
+    match self.guard {
+      None => match test {
+        Some(test) =>
+          Expr::If(Arc::new(test), Arc::new(bind((*self.body).clone())), Arc::new(next)),
+        None => bind((*self.body).clone()),
+      },
+      // next is needed both when the pattern and the guard fail, so it is put in a function,
+      // which also keeps it from seeing the names the pattern binds
+      Some(guard) => {
+        let call_next = Arc::new(Expr::Call(Arc::new(Expr::Variable(String::from("#:next"))),
+          Vec::new()));
+        let matched = bind(Expr::If(guard, self.body, Arc::clone(&call_next)));
+        let next = Expr::Defn(Arc::new(Defn{name: String::new(), params: Vec::new(),
+          body: Arc::new(next)}));
+        Expr::Assign(Assign::Local(String::from("#:next"), Arc::new(next), Arc::new(match test {
+          Some(test) => Expr::If(Arc::new(test), Arc::new(matched), call_next),
+          None => matched,
+        })))
+      },
+    }
+  }
+}
+
+// Matches the value against each arm in turn, raising an error if none of them match
+pub fn lower(value: Expr, arms: Vec<Arm>) -> Expr {
+  let path = Expr::Variable(String::from("#:match"));
+  let no_match = call(no_match, vec!(path.clone()));
+  let arms = arms.into_iter().rev().fold(no_match, |next, arm| arm.lower(&path, next));
+  Expr::Assign(Assign::Local(String::from("#:match"), Arc::new(value), Arc::new(arms)))
+}
+
+// Binds the names in the pattern to the parts of the value for the body
+pub fn destructure(pattern: Pattern, value: Expr, body: Arc<Expr>) -> Expr {
+  match pattern {
+    Pattern::Bind(name) => Expr::Assign(Assign::Local(name, Arc::new(value), body)),
+    pattern => lower(value, vec!(Arm{pattern, guard: None, body})),
+  }
+}
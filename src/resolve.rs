@@ -0,0 +1,166 @@
+use ast::{Expr, Assign, Defn, ParamType, Try, GlobalEnv};
+use error::RuntimeError;
+use std::sync::Arc;
+
+// Replaces variable names with where they will be found at runtime. Locals become the number of
+// frames up from the innermost one and the slot within that frame, with frames laid out as:
+//   a function call: the function itself, followed by its parameters
+//   a let or catch: the bound value
+//   a multiple binding let or letrec: the bound values, in order
+// Any other name must be a global, either one which already exists or one which the expression
+// itself assigns to.
+struct Resolver<'a> {
+  g_env: &'a GlobalEnv,
+  scopes: Vec<Vec<String>>,
+}
+
+impl<'a> Resolver<'a> {
+  fn variable(&self, name: &str) -> Result<Expr, RuntimeError> {
+    let local = self.scopes.iter().rev().enumerate().filter_map(|(depth, scope)|
+      scope.iter().rposition(|n| n == name).map(|i| (depth, i))).next();
+    match local {
+      Some((depth, i)) => Ok(Expr::Local(depth, i)),
+      None => match self.g_env.index_of(name) {
+        Some(i) => Ok(Expr::Global(i)),
+        None => Err(RuntimeError::UnboundVariable(name.to_string())),
+      },
+    }
+  }
+  fn within(&mut self, frame: Vec<String>, expr: &Expr) -> Result<Arc<Expr>, RuntimeError> {
+    self.scopes.push(frame);
+    let resolved = self.resolve(expr);
+    self.scopes.pop();
+    resolved.map(Arc::new)
+  }
+  fn resolve(&mut self, expr: &Expr) -> Result<Expr, RuntimeError> {
+    Ok(match expr {
+      Expr::Value(_) | Expr::Local(..) | Expr::Global(_) => expr.clone(),
+      Expr::Variable(name) => self.variable(name)?,
+      Expr::Defn(defn) => {
+        let mut frame = vec!(defn.name.to_string());
+        frame.extend(defn.params.iter().map(|p| match p {
+          ParamType::Singular(name) | ParamType::Rest(name) => name.to_string(),
+        }));
+        Expr::Defn(Arc::new(Defn{
+          name: defn.name.to_string(),
+          params: defn.params.clone(),
+          body: self.within(frame, &defn.body)?,
+        }))
+      },
+      Expr::Call(operator, operands) => Expr::Call(Arc::new(self.resolve(operator)?),
+        operands.iter().map(|o| self.resolve(o).map(Arc::new)).collect::<Result<_, _>>()?),
+      Expr::Assign(Assign::Local(name, val, body)) => Expr::Assign(Assign::Local(
+        name.to_string(), Arc::new(self.resolve(val)?),
+        self.within(vec!(name.to_string()), body)?)),
+      Expr::Assign(Assign::Global(name, val)) =>
+        Expr::Assign(Assign::Global(name.to_string(), Arc::new(self.resolve(val)?))),
+      Expr::Assign(Assign::Locals(bindings, body)) => Expr::Assign(Assign::Locals(
+        bindings.iter().map(|(name, val)| Ok((name.to_string(), Arc::new(self.resolve(val)?))))
+          .collect::<Result<_, _>>()?,
+        self.within(bindings.iter().map(|(name, _)| name.to_string()).collect(), body)?)),
+      Expr::Assign(Assign::Set(target, val)) =>
+        Expr::Assign(Assign::Set(Arc::new(self.resolve(target)?), Arc::new(self.resolve(val)?))),
+      Expr::Assign(Assign::Rec(bindings, body)) => {
+        self.scopes.push(bindings.iter().map(|(name, _)| name.to_string()).collect());
+        let resolved = bindings.iter()
+          .map(|(name, val)| Ok((name.to_string(), Arc::new(self.resolve(val)?))))
+          .collect::<Result<_, _>>()
+          .and_then(|bindings| Ok(Expr::Assign(Assign::Rec(bindings,
+            Arc::new(self.resolve(body)?)))));
+        self.scopes.pop();
+        resolved?
+      },
+      Expr::If(cond, pred, fallback) => Expr::If(Arc::new(self.resolve(cond)?),
+        Arc::new(self.resolve(pred)?), Arc::new(self.resolve(fallback)?)),
+      Expr::Seq(exprs) =>
+        Expr::Seq(exprs.iter().map(|e| self.resolve(e).map(Arc::new)).collect::<Result<_, _>>()?),
+      Expr::Try(t) => Expr::Try(Arc::new(Try{
+        body: Arc::new(self.resolve(&t.body)?),
+        catch: match &t.catch {
+          Some((name, handler)) =>
+            Some((name.to_string(), self.within(vec!(name.to_string()), handler)?)),
+          None => None,
+        },
+        finally: match &t.finally {
+          Some(finally) => Some(Arc::new(self.resolve(finally)?)),
+          None => None,
+        },
+      })),
+    })
+  }
+}
+
+// Gives every global the expression assigns to a slot, so that it can be referred to before the
+// assignment has run.
+fn declare_globals(expr: &Expr, g_env: &mut GlobalEnv) {
+  match expr {
+    Expr::Value(_) | Expr::Variable(_) | Expr::Local(..) | Expr::Global(_) => (),
+    Expr::Defn(defn) => declare_globals(&defn.body, g_env),
+    Expr::Call(operator, operands) => {
+      declare_globals(operator, g_env);
+      operands.iter().for_each(|o| declare_globals(o, g_env));
+    },
+    Expr::Assign(Assign::Local(_, val, body)) => {
+      declare_globals(val, g_env);
+      declare_globals(body, g_env);
+    },
+    Expr::Assign(Assign::Global(name, val)) => {
+      g_env.declare(name);
+      declare_globals(val, g_env);
+    },
+    Expr::Assign(Assign::Set(_, val)) => declare_globals(val, g_env),
+    Expr::Assign(Assign::Locals(bindings, body)) | Expr::Assign(Assign::Rec(bindings, body)) => {
+      bindings.iter().for_each(|(_, val)| declare_globals(val, g_env));
+      declare_globals(body, g_env);
+    },
+    Expr::If(cond, pred, fallback) =>
+      [cond, pred, fallback].iter().for_each(|e| declare_globals(e, g_env)),
+    Expr::Seq(exprs) => exprs.iter().for_each(|e| declare_globals(e, g_env)),
+    Expr::Try(t) => {
+      declare_globals(&t.body, g_env);
+      t.catch.iter().for_each(|(_, handler)| declare_globals(handler, g_env));
+      t.finally.iter().for_each(|finally| declare_globals(finally, g_env));
+    },
+  }
+}
+
+// Resolves a sequence of top level expressions, which may refer to globals assigned by any of
+// the others.
+pub fn program(exprs: &[Expr], g_env: &mut GlobalEnv) -> Result<Vec<Expr>, RuntimeError> {
+  exprs.iter().for_each(|e| declare_globals(e, g_env));
+  exprs.iter().map(|e| Resolver{g_env, scopes: Vec::new()}.resolve(e)).collect()
+}
+
+impl Expr {
+  pub fn resolve(&self, g_env: &mut GlobalEnv) -> Result<Expr, RuntimeError> {
+    declare_globals(self, g_env);
+    Resolver{g_env, scopes: Vec::new()}.resolve(self)
+  }
+}
+
+#[test]
+fn test_resolve() {
+  use lisp_parse::parse;
+
+  let mut g_env = GlobalEnv::new();
+  let src = "(let f (defn f x (let y x (if y (f y) (g x)))))";
+  let expr = parse(String::from(src)).unwrap()[0].to_ast().unwrap();
+  match expr.resolve(&mut g_env) {
+    Err(RuntimeError::UnboundVariable(ref name)) if name == "g" => (),
+    other => panic!("Expected g to be unbound, got {:?}", other),
+  }
+
+  let src = format!("{} (let g (defn g z z))", src);
+  let exprs: Vec<_> = parse(src).unwrap().iter().map(|t| t.to_ast().unwrap()).collect();
+  let resolved = program(&exprs, &mut g_env).unwrap();
+  let (f, g) = (g_env.index_of("f").unwrap(), g_env.index_of("g").unwrap());
+  let local = |depth, i| Arc::new(Expr::Local(depth, i));
+  let body = Expr::If(local(0, 0), Arc::new(Expr::Call(local(1, 0), vec!(local(0, 0)))),
+    Arc::new(Expr::Call(Arc::new(Expr::Global(g)), vec!(local(1, 1)))));
+  let defn = Defn{name: String::from("f"), params: vec!(ParamType::Singular(String::from("x"))),
+    body: Arc::new(Expr::Assign(Assign::Local(String::from("y"), local(0, 1), Arc::new(body))))};
+  let expected = format!("{:?}", Expr::Assign(Assign::Global(String::from("f"),
+    Arc::new(Expr::Defn(Arc::new(defn))))));
+  assert_eq!(format!("{:?}", resolved[0]), expected);
+  assert!(f != g);
+}
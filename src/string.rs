@@ -0,0 +1,186 @@
+use ast::{Type, GlobalEnv};
+use error::{Arity, RuntimeError};
+use number::Number;
+use list::{items, index};
+use std::sync::Arc;
+use std::borrow::Borrow;
+
+fn string(v: &Arc<Type>) -> Result<&str, RuntimeError> {
+  match v.borrow() {
+    Type::Str(s) => Ok(s),
+    _ => Err(RuntimeError::mismatch("string", v)),
+  }
+}
+
+fn character(v: &Arc<Type>) -> Result<char, RuntimeError> {
+  match v.borrow() {
+    Type::Char(c) => Ok(*c),
+    _ => Err(RuntimeError::mismatch("char", v)),
+  }
+}
+
+fn new_string(s: String) -> Arc<Type> {
+  Arc::new(Type::Str(s))
+}
+
+
+// Fills in a format string, where ~a is replaced by the next argument as it is displayed, ~s by
+// the next argument as it is written, ~% by a newline and ~~ by a tilde
+fn format(template: &Arc<Type>, args: &[Arc<Type>]) -> Result<String, RuntimeError> {
+  let mut out = String::new();
+  let mut needed = 0;
+  let mut chars = string(template)?.chars();
+  while let Some(c) = chars.next() {
+    match (c, if c == '~' { chars.next() } else { None }) {
+      ('~', Some('%')) => out.push('\n'),
+      ('~', Some('~')) => out.push('~'),
+      ('~', Some(d @ 'a')) | ('~', Some(d @ 's')) => {
+        if let Some(arg) = args.get(needed) {
+          out.push_str(&if d == 'a' { arg.display() } else { arg.to_string() });
+        }
+        needed += 1;
+      },
+      ('~', _) => return Err(RuntimeError::Raised(Arc::new(Type::Error(
+        String::from("Expected ~a, ~s, ~% or ~~ in format string"), Arc::clone(template))))),
+      (c, _) => out.push(c),
+    }
+  }
+  Arity::Exactly(needed + 1).check(args.len() + 1)?;
+  Ok(out)
+}
+
+// Defines a builtin which maps one string to another
+macro_rules! convert {
+  ($e: expr, $name: expr, $f: expr) => {
+    $e.define($name, Type::new_builtin(Arity::Exactly(1), |x, _| {
+      Ok(new_string($f(string(&x[0])?)))
+    }));
+  };
+}
+
+// Defines a builtin which gives back whether its one character is of some kind
+macro_rules! char_class {
+  ($e: expr, $name: expr, $f: expr) => {
+    $e.define($name, Type::new_builtin(Arity::Exactly(1), |x, _| {
+      Ok(Arc::new(Type::Bool($f(character(&x[0])?))))
+    }));
+  };
+}
+
+// Defines a builtin which maps one character to another, keeping it the same if the mapping
+// would give more than one character
+macro_rules! char_case {
+  ($e: expr, $name: expr, $f: expr) => {
+    $e.define($name, Type::new_builtin(Arity::Exactly(1), |x, _| {
+      let c = character(&x[0])?;
+      let mut mapped = $f(c);
+      Ok(Arc::new(Type::Char(match (mapped.next(), mapped.next()) {
+        (Some(m), None) => m,
+        _ => c,
+      })))
+    }));
+  };
+}
+
+pub fn define(e: &mut GlobalEnv) {
+  e.define("string-append", Type::new_rust_closure(|x, _|
+    Ok(new_string(x.iter().map(string).collect::<Result<Vec<_>, _>>()?.concat()))));
+
+  e.define("string-length", Type::new_builtin(Arity::Exactly(1), |x, _| {
+    Ok(Type::new_number(string(&x[0])?.chars().count() as i64))
+  }));
+
+  // (substring s start end) gives the characters from start up to but not including end, which
+  // is the end of the string if it is left out
+  e.define("substring", Type::new_builtin(Arity::Between(2, 3), |x, _| {
+    let chars: Vec<char> = string(&x[0])?.chars().collect();
+    let start = index(&x[1])?;
+    let end = match x.get(2) {
+      Some(end) => index(end)?,
+      None => chars.len(),
+    };
+    if end > chars.len() {
+      return Err(RuntimeError::IndexOutOfRange{index: end, length: chars.len()})
+    }
+    if start > end {
+      return Err(RuntimeError::IndexOutOfRange{index: start, length: end})
+    }
+    Ok(new_string(chars[start..end].iter().collect()))
+  }));
+
+  // (string-split s) splits on whitespace, and (string-split s sep) on each separator
+  e.define("string-split", Type::new_builtin(Arity::Between(1, 2), |x, _| {
+    let s = string(&x[0])?;
+    let parts: Vec<&str> = match x.get(1) {
+      Some(sep) => match string(sep)? {
+        "" => return Err(RuntimeError::mismatch("non-empty string", sep)),
+        sep => s.split(sep).collect(),
+      },
+      None => s.split_whitespace().collect(),
+    };
+    Ok(Type::new_list(parts.into_iter().map(|p| new_string(p.to_string())).collect()))
+  }));
+
+  // (string-join l) joins a list of strings with spaces, and (string-join l sep) with sep
+  e.define("string-join", Type::new_builtin(Arity::Between(1, 2), |x, _| {
+    let sep = match x.get(1) {
+      Some(sep) => string(sep)?,
+      None => " ",
+    };
+    let parts = items(&x[0])?;
+    Ok(new_string(parts.iter().map(string).collect::<Result<Vec<_>, _>>()?.join(sep)))
+  }));
+
+  // Reads a number the same way the parser does, giving back f if the string is not one
+  e.define("string->number", Type::new_builtin(Arity::Exactly(1), |x, _| {
+    Ok(match Number::parse(string(&x[0])?) {
+      Some(n) => Type::new_number(n),
+      None => Arc::new(Type::Bool(false)),
+    })
+  }));
+  e.define("number->string", Type::new_builtin(Arity::Exactly(1), |x, _| {
+    match x[0].borrow() {
+      Type::Number(n) => Ok(new_string(n.to_string())),
+      _ => Err(RuntimeError::mismatch("number", &x[0])),
+    }
+  }));
+
+  e.define("string->list", Type::new_builtin(Arity::Exactly(1), |x, _| {
+    Ok(Type::new_list(string(&x[0])?.chars().map(|c| Arc::new(Type::Char(c))).collect()))
+  }));
+  e.define("list->string", Type::new_builtin(Arity::Exactly(1), |x, _| {
+    Ok(new_string(items(&x[0])?.iter().map(character).collect::<Result<_, _>>()?))
+  }));
+
+  // char->fixnum and fixnum->char are the names the compiler uses
+  let to_integer = Type::new_builtin(Arity::Exactly(1), |x, _| {
+    Ok(Type::new_number(i64::from(character(&x[0])? as u32)))
+  });
+  let to_char = Type::new_builtin(Arity::Exactly(1), |x, _| {
+    match x[0].borrow() {
+      Type::Number(Number::Int(n)) if *n >= 0 && *n <= i64::from(u32::max_value()) =>
+        ::std::char::from_u32(*n as u32).map(|c| Arc::new(Type::Char(c))),
+      _ => None,
+    }.ok_or_else(|| RuntimeError::mismatch("character code", &x[0]))
+  });
+  e.define("char->integer", Arc::clone(&to_integer));
+  e.define("char->fixnum", to_integer);
+  e.define("integer->char", Arc::clone(&to_char));
+  e.define("fixnum->char", to_char);
+
+  e.define("char?", Type::new_builtin(Arity::Exactly(1), |x, _| {
+    Ok(Arc::new(Type::Bool(if let Type::Char(_) = x[0].borrow() { true } else { false })))
+  }));
+  char_class!(e, "char-alphabetic?", char::is_alphabetic);
+  char_class!(e, "char-numeric?", char::is_numeric);
+  char_class!(e, "char-whitespace?", char::is_whitespace);
+  char_case!(e, "char-upcase", char::to_uppercase);
+  char_case!(e, "char-downcase", char::to_lowercase);
+
+  convert!(e, "string-upcase", str::to_uppercase);
+  convert!(e, "string-downcase", str::to_lowercase);
+
+  e.define("format", Type::new_builtin(Arity::AtLeast(1), |x, _| {
+    format(&x[0], &x[1..]).map(new_string)
+  }));
+}
@@ -1,64 +1,605 @@
-use lisp_parse::{Token};
-use ast::{Expr, Type, Defn, ParamType, Assign};
+use lisp_parse::{Token, Span, Brace, read_char};
+use ast::{Expr, Type, Defn, ParamType, Assign, Try, RustClosureFn};
+use pattern::{self, Pattern, Arm};
+use number::Number;
+use default_env::{list, append, equal, pair};
+use list::append_map;
+use map::{hash_map, hash_set, map_from, set_from};
 use std::sync::Arc;
+use std::fmt;
+use std::error::Error;
+
+// A well-formed token which does not make up a valid expression
+#[derive(Debug)]
+pub struct SyntaxError {
+  pub message: String,
+  pub span: Span,
+}
+
+impl SyntaxError {
+  pub(crate) fn at<T>(t: &Token, message: &str) -> Result<T, SyntaxError> {
+    Err(SyntaxError{message: message.to_string(), span: t.span()})
+  }
+}
+
+impl fmt::Display for SyntaxError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "{} at line {}, column {}", self.message, self.span.line, self.span.col)
+  }
+}
+
+impl Error for SyntaxError {}
+
+// Words which evaluate to themselves, whether quoted or not. A word which looks like a character
+// but does not name one is an error rather than a symbol.
+pub(crate) fn literal(t: &Token, s: &str) -> Result<Option<Arc<Type>>, SyntaxError> {
+  Ok(match s {
+    "[]" => Some(Type::new_empty_list()),
+    "t" => Some(Arc::new(Type::Bool(true))),
+    "f" => Some(Arc::new(Type::Bool(false))),
+    s if s.starts_with("#\\") => match read_char(s) {
+      Some(c) => Some(Arc::new(Type::Char(c))),
+      None => return SyntaxError::at(t, "Unknown character name"),
+    },
+    s => Number::parse(s).map(Type::new_number),
+  })
+}
+
+// If the group is a dotted pair (a . b), returns its two parts
+fn dotted(g: &[Token]) -> Option<(&Token, &Token)> {
+  match g {
+    [a, Token::Word(dot, _), b] if dot == "." => Some((a, b)),
+    _ => None,
+  }
+}
+
+// The function which builds a map or set literal out of its items. A map literal must have a
+// value for each key.
+fn collection(brace: Brace, items: &[Token]) -> Result<RustClosureFn, SyntaxError> {
+  match (brace, items.last()) {
+    (Brace::Map, Some(last)) if items.len() % 2 != 0 =>
+      SyntaxError::at(last, "Expected a value for each key of the map"),
+    (Brace::Map, _) => Ok(hash_map),
+    (Brace::Set, _) => Ok(hash_set),
+  }
+}
+
+// If the group is a reader shorthand such as (quote x), returns the form's name and its datum
+fn quote_form(g: &[Token]) -> Option<(&str, &Token)> {
+  match (g.get(0), g.get(1)) {
+    (Some(Token::Word(s, _)), Some(datum)) if g.len() == 2 => match &s[..] {
+      "quote" | "quasiquote" | "unquote" | "unquote-splicing" => Some((s, datum)),
+      _ => None,
+    },
+    _ => None,
+  }
+}
+
+// Builds the expression which constructs a quasiquoted datum, evaluating the unquoted parts.
+// Depth counts how many quasiquotes deep the datum is nested within the outermost one.
+fn quasiquote(t: &Token, depth: usize) -> Result<Expr, SyntaxError> {
+  let g = match t {
+    Token::Group(g, _) => g,
+    Token::Braced(brace, items, _) => return Ok(Expr::Call(
+      Arc::new(Expr::Value(Type::new_rust_closure(collection(*brace, items)?))),
+      items.iter().map(|item| quasiquote(item, depth).map(Arc::new)).collect::<Result<_, _>>()?)),
+    _ => return Ok(Expr::Value(t.to_datum()?)),
+  };
+  let nested = |name: &str, inner: &Token, depth: usize| Ok(Expr::Call(
+    Arc::new(Expr::Value(Type::new_rust_closure(list))),
+    vec!(Arc::new(Expr::Value(Arc::new(Type::Symbol(name.to_string())))),
+      Arc::new(quasiquote(inner, depth)?))));
+  match quote_form(g) {
+    Some(("unquote", inner)) if depth == 0 => return inner.to_ast(),
+    Some(("unquote-splicing", _)) if depth == 0 =>
+      return SyntaxError::at(t, "Cannot splice outside of a list"),
+    Some((name @ "unquote", inner)) | Some((name @ "unquote-splicing", inner)) =>
+      return nested(name, inner, depth - 1),
+    Some(("quasiquote", inner)) => return nested("quasiquote", inner, depth + 1),
+    _ => (),
+  };
+  if let Some((a, b)) = dotted(g) {
+    return Ok(Expr::Call(Arc::new(Expr::Value(Type::new_rust_closure(pair))),
+      vec!(Arc::new(quasiquote(a, depth)?), Arc::new(quasiquote(b, depth)?))))
+  }
+  let parts = g.iter().map(|item| Ok(Arc::new(match item {
+    Token::Group(inner, _) => match quote_form(inner) {
+      Some(("unquote-splicing", spliced)) if depth == 0 => spliced.to_ast()?,
+      _ => Expr::Call(Arc::new(Expr::Value(Type::new_rust_closure(list))),
+        vec!(Arc::new(quasiquote(item, depth)?))),
+    },
+    Token::Braced(..) => Expr::Call(Arc::new(Expr::Value(Type::new_rust_closure(list))),
+      vec!(Arc::new(quasiquote(item, depth)?))),
+    _ => Expr::Value(Type::new_list(vec!(item.to_datum()?))),
+  }))).collect::<Result<Vec<_>, _>>()?;
+  Ok(Expr::Call(Arc::new(Expr::Value(Type::new_rust_closure(append))), parts))
+}
+
+fn name_of(t: Option<&Token>, parent: &Token) -> Result<String, SyntaxError> {
+  match t {
+    Some(Token::Word(s, _)) => Ok(s.to_string()),
+    Some(t) => SyntaxError::at(t, "Must assign to name"),
+    None => SyntaxError::at(parent, "Must assign to name"),
+  }
+}
+
+// Parses (try body (catch name handler) (finally cleanup)), where either clause may be left out
+fn try_form(t: &Token, g: &[Token]) -> Result<Expr, SyntaxError> {
+  let body = match g.get(1) {
+    Some(body) => Arc::new(body.to_ast()?),
+    None => return SyntaxError::at(t, "Try must have a body"),
+  };
+  let mut catch = None;
+  let mut finally = None;
+  for clause in &g[2..] {
+    let parts = match clause {
+      Token::Group(parts, _) => parts,
+      _ => return SyntaxError::at(clause, "Expected a catch or finally clause"),
+    };
+    match (parts.get(0), parts.len()) {
+      (Some(Token::Word(s, _)), 3) if s == "catch" && catch.is_none() && finally.is_none() =>
+        catch = Some((name_of(parts.get(1), clause)?, Arc::new(parts[2].to_ast()?))),
+      (Some(Token::Word(s, _)), 2) if s == "finally" && finally.is_none() =>
+        finally = Some(Arc::new(parts[1].to_ast()?)),
+      _ => return SyntaxError::at(clause,
+        "Expected (catch name handler) followed by (finally cleanup)"),
+    }
+  }
+  if catch.is_none() && finally.is_none() {
+    return SyntaxError::at(t, "Try must have a catch or finally clause")
+  }
+  Ok(Expr::Try(Arc::new(Try{body, catch, finally})))
+}
+
+// Parses params which are names, where &name takes the rest of the arguments. A word which
+// evaluates to itself, such as 1 or t, cannot be a name.
+fn params(items: &[Token]) -> Result<Vec<ParamType>, SyntaxError> {
+  items.iter().map(|it| {
+    let (name, rest) = match it {
+      Token::Word(s, _) if s.starts_with("&") => (&s[1..], true),
+      Token::Word(s, _) => (&s[..], false),
+      Token::Str(..) | Token::Group(..) | Token::Braced(..) =>
+        return SyntaxError::at(it, "Can only have string params"),
+    };
+    if literal(it, name)?.is_some() {
+      return SyntaxError::at(it, &format!("{} cannot be the name of a param", name))
+    }
+    Ok(if rest { ParamType::Rest(name.to_string()) } else { ParamType::Singular(name.to_string()) })
+  }).collect()
+}
+
+// The names of the special forms, which cannot be used as variables
+pub(crate) fn is_reserved(s: &str) -> bool {
+  match s {
+    "let" | "defn" | "if" | "quote" | "quasiquote" | "unquote" | "unquote-splicing" |
+    "try" | "catch" | "finally" | "lambda" | "fn" | "defmacro" | "macroexpand" | "cond" |
+    "case" | "else" | "when" | "unless" | "and" | "or" | "begin" | "do" | "let*" | "letrec" |
+    "letrec*" | "set!" | "match" | "for" => true,
+    _ => false,
+  }
+}
+
+// Whether a token before the body of a defn is one of its params, which are words along with
+// lists, which are patterns. The body starts at the first special form, so a body which starts
+// by calling a function goes in a begin.
+pub(crate) fn is_param(t: &Token) -> bool {
+  match t {
+    Token::Word(..) => true,
+    Token::Group(g, _) => match g.get(0) {
+      Some(Token::Word(s, _)) => !is_reserved(s),
+      Some(_) => true,
+      // () is unit rather than a pattern
+      None => false,
+    },
+    Token::Str(..) | Token::Braced(..) => false,
+  }
+}
+
+// Parses params, where a param which is a list is a pattern the argument is destructured by in
+// the body
+fn pattern_params(items: &[Token], body: Arc<Expr>)
+  -> Result<(Vec<ParamType>, Arc<Expr>), SyntaxError> {
+  let ps = items.iter().map(|p| match p {
+    Token::Group(..) => Ok((Pattern::parse(p)?, false)),
+    _ => match params(&[p.clone()])?.remove(0) {
+      ParamType::Singular(name) => Ok((Pattern::Bind(name), false)),
+      ParamType::Rest(name) => Ok((Pattern::Bind(name), true)),
+    },
+  }).collect::<Result<Vec<_>, _>>()?;
+  let (ps, body) = hidden_names(ps, "arg", body);
+  Ok((ps.into_iter().map(|(name, rest)|
+    if rest { ParamType::Rest(name) } else { ParamType::Singular(name) }).collect(), body))
+}
+
+fn is_group(t: &Token) -> bool {
+  match t {
+    Token::Group(..) => true,
+    _ => false,
+  }
+}
+
+fn if_expr(cond: Expr, pred: Expr, fallback: Expr) -> Expr {
+  Expr::If(Arc::new(cond), Arc::new(pred), Arc::new(fallback))
+}
+
+fn boolean(b: bool) -> Expr {
+  Expr::Value(Arc::new(Type::Bool(b)))
+}
+
+// Parses the clauses of a cond or case, where each clause is (test body) and the last may be
+// (else body). The test of each clause is turned into a condition by the given function.
+fn clauses<F>(t: &Token, items: &[Token], test: &F) -> Result<Expr, SyntaxError>
+  where F: Fn(&Token) -> Result<Expr, SyntaxError> {
+  let (clause, rest) = match items.split_first() {
+    Some(split) => split,
+    None => return Ok(Expr::Value(Type::unit())),
+  };
+  match clause {
+    Token::Group(parts, _) if parts.len() == 2 => match &parts[0] {
+      Token::Word(s, _) if s == "else" && rest.is_empty() => parts[1].to_ast(),
+      Token::Word(s, _) if s == "else" => SyntaxError::at(clause, "Else must be the last clause"),
+      cond => Ok(if_expr(test(cond)?, parts[1].to_ast()?, clauses(t, rest, test)?)),
+    },
+    _ => SyntaxError::at(clause, "Expected a clause of a test followed by a body"),
+  }
+}
+
+// Lowers the forms which are sugar for nested ifs
+fn derived(t: &Token, form: &str, args: &[Token]) -> Result<Expr, SyntaxError> {
+  Ok(match (form, args) {
+    ("when", [cond, body]) => if_expr(cond.to_ast()?, body.to_ast()?, Expr::Value(Type::unit())),
+    ("unless", [cond, body]) =>
+      if_expr(cond.to_ast()?, Expr::Value(Type::unit()), body.to_ast()?),
+    ("when", _) | ("unless", _) =>
+      return SyntaxError::at(t, &format!("{} must have a condition and a body", form)),
+    // only t is true, so the connectives always give back a boolean
+    ("and", _) => args.iter().rev().fold(Ok(boolean(true)), |acc, arg|
+      Ok(if_expr(arg.to_ast()?, acc?, boolean(false))))?,
+    ("or", _) => args.iter().rev().fold(Ok(boolean(false)), |acc, arg|
+      Ok(if_expr(arg.to_ast()?, boolean(true), acc?)))?,
+    ("cond", _) => clauses(t, args, &|cond| cond.to_ast())?,
+    // the key is bound to a name no program can refer to, so it is only evaluated once
+    ("case", _) => {
+      let (key, rest) = match args.split_first() {
+        Some(split) => split,
+        None => return SyntaxError::at(t, "Case must have a key"),
+      };
+      let is = |datum: &Token| Ok(Expr::Call(Arc::new(Expr::Value(Type::new_rust_closure(equal))),
+        vec!(Arc::new(Expr::Variable(String::from("#:case"))),
+          Arc::new(Expr::Value(datum.to_datum()?)))));
+      let body = clauses(t, rest, &|datums| match datums {
+        Token::Group(ds, _) => ds.iter().rev().fold(Ok(boolean(false)), |acc, d|
+          Ok(if_expr(is(d)?, boolean(true), acc?))),
+        datum => is(datum),
+      })?;
+      Expr::Assign(Assign::Local(String::from("#:case"), Arc::new(key.to_ast()?), Arc::new(body)))
+    },
+    _ => unreachable!("{} is not a derived form", form),
+  })
+}
+
+// Parses the bindings of a let, let* or letrec, ((name value)...)
+fn bindings(t: &Token) -> Result<Vec<(Pattern, Arc<Expr>)>, SyntaxError> {
+  let items = match t {
+    Token::Group(items, _) => items,
+    _ => return SyntaxError::at(t, "Expected a list of bindings"),
+  };
+  items.iter().map(|binding| match binding {
+    Token::Group(parts, _) if parts.len() == 2 =>
+      Ok((Pattern::parse(&parts[0])?, Arc::new(parts[1].to_ast()?))),
+    _ => SyntaxError::at(binding, "Expected a binding of a name and a value"),
+  }).collect()
+}
+
+// Gives each pattern which is not just a name a hidden name of its own, then destructures the
+// values bound to those names around the body
+fn hidden_names<T>(patterns: Vec<(Pattern, T)>, kind: &str, body: Arc<Expr>)
+  -> (Vec<(String, T)>, Arc<Expr>) {
+  let mut names = Vec::new();
+  let mut destructured = Vec::new();
+  for (i, (p, t)) in patterns.into_iter().enumerate() {
+    match p {
+      Pattern::Bind(name) => names.push((name, t)),
+      p => {
+        let name = format!("#:{}{}", kind, i);
+        destructured.push((p, Expr::Variable(name.to_string())));
+        names.push((name, t));
+      },
+    }
+  }
+  let body = destructured.into_iter().rev()
+    .fold(body, |body, (p, value)| Arc::new(pattern::destructure(p, value, body)));
+  (names, body)
+}
+
+// Parses (match value (pattern body...) (pattern if guard body...)...)
+fn match_form(t: &Token, g: &[Token]) -> Result<Expr, SyntaxError> {
+  let value = match g.get(1) {
+    Some(value) => value.to_ast()?,
+    None => return SyntaxError::at(t, "Match must have a value"),
+  };
+  let mut arms: Vec<Arm> = Vec::new();
+  for arm in &g[2..] {
+    let parts = match arm {
+      Token::Group(parts, _) if parts.len() >= 2 => parts,
+      _ => return SyntaxError::at(arm, "Expected an arm of a pattern followed by a body"),
+    };
+    let pattern = Pattern::parse(&parts[0])?;
+    let (guard, rest) = match &parts[1] {
+      Token::Word(s, _) if s == "if" && parts.len() > 3 =>
+        (Some(Arc::new(parts[2].to_ast()?)), &parts[3..]),
+      _ => (None, &parts[1..]),
+    };
+    if arms.iter().any(|a| a.guard.is_none() && a.pattern.covers(&pattern)) {
+      return SyntaxError::at(arm, "Unreachable match arm, an earlier arm matches all it does")
+    }
+    arms.push(Arm{pattern, guard, body: body(arm, rest)?});
+  }
+  Ok(pattern::lower(value, arms))
+}
+
+// Wraps a clause of a for around the expression giving the list made by the clauses after it
+fn for_clause(clause: &Token, inner: Expr) -> Result<Expr, SyntaxError> {
+  let empty = || Expr::Value(Type::new_empty_list());
+  let parts = match clause {
+    Token::Group(parts, _) => parts,
+    _ => return SyntaxError::at(clause, "Expected a clause of a for"),
+  };
+  match (parts.get(0), parts.len()) {
+    (Some(Token::Word(s, _)), 2) if s == "when" => Ok(if_expr(parts[1].to_ast()?, inner, empty())),
+    (Some(Token::Word(s, _)), 2) if s == "unless" =>
+      Ok(if_expr(parts[1].to_ast()?, empty(), inner)),
+    (Some(Token::Word(s, _)), 3) if s == "let" =>
+      Ok(pattern::destructure(Pattern::parse(&parts[1])?, parts[2].to_ast()?, Arc::new(inner))),
+    // the rest of the clauses are run for each item, and items the pattern does not match are
+    // skipped
+    (Some(p), 2) => {
+      let (param, body) = match Pattern::parse(p)? {
+        Pattern::Bind(name) => (name, inner),
+        pattern => (String::from("#:item"), pattern::lower(Expr::Variable(String::from("#:item")),
+          vec!(Arm{pattern, guard: None, body: Arc::new(inner)},
+            Arm{pattern: Pattern::Wildcard, guard: None, body: Arc::new(empty())}))),
+      };
+      let each = Defn{name: String::new(), params: vec!(ParamType::Singular(param)),
+        body: Arc::new(body)};
+      Ok(Expr::Call(Arc::new(Expr::Value(Type::new_rust_closure(append_map))),
+        vec!(Arc::new(Expr::Defn(Arc::new(each))), Arc::new(parts[1].to_ast()?))))
+    },
+    _ => SyntaxError::at(clause,
+      "Expected (pattern list), (when test), (unless test) or (let pattern value)"),
+  }
+}
+
+// Parses (for (clause...) clause... body), a list comprehension giving the list of what the body
+// evaluates to for each item of the generators (pattern list), which are nested in order. Filters
+// (when test) and (unless test), and bindings (let pattern value), can also be put in between,
+// either in the list of clauses or after it.
+fn for_form(t: &Token, g: &[Token]) -> Result<Expr, SyntaxError> {
+  let (first, rest, last) = match (g.get(1), g.split_last()) {
+    (Some(Token::Group(first, _)), Some((last, init))) if g.len() > 2 => (first, &init[2..], last),
+    _ => return SyntaxError::at(t, "For must have a list of clauses and a body"),
+  };
+  let body = Expr::Call(Arc::new(Expr::Value(Type::new_rust_closure(list))),
+    vec!(Arc::new(last.to_ast()?)));
+  first.iter().chain(rest).rev().fold(Ok(body), |inner, clause| for_clause(clause, inner?))
+}
+
+// A body of one or more expressions, which are evaluated in order
+fn body(t: &Token, items: &[Token]) -> Result<Arc<Expr>, SyntaxError> {
+  match items.len() {
+    0 => SyntaxError::at(t, "Expected a body"),
+    1 => Ok(Arc::new(items[0].to_ast()?)),
+    _ => Ok(Arc::new(Expr::Seq(sub_asts(items)?))),
+  }
+}
+
+fn sub_asts(items: &[Token]) -> Result<Vec<Arc<Expr>>, SyntaxError> {
+  items.iter().map(|it| it.to_ast().map(Arc::new)).collect()
+}
+
+fn datums(items: &[Token]) -> Result<Vec<Arc<Type>>, SyntaxError> {
+  items.iter().map(|it| it.to_datum()).collect()
+}
 
 impl Token {
-  pub fn to_ast(&self) -> Expr {
-    println!("{:?}", self);
-    match self {
-      Token::Word(s) => match &s[..] {
-        "let" | "defn" | "if" => panic!("Reserved keyword used"),
-        "[]" => Expr::Value(Type::new_empty_list()),
-        "t" => Expr::Value(Arc::new(Type::Bool(true))),
-        "f" => Expr::Value(Arc::new(Type::Bool(false))),
-        s if s.parse::<f32>().is_ok() =>
-          Expr::Value(Type::new_number(s.parse::<f32>().unwrap())),
-        s if s.starts_with("\"") && s.ends_with("\"") =>
-          Expr::Value(Arc::new(Type::Str(s.to_string()))),
-        s => Expr::Variable(s.to_string()),
+  // Converts the token into the data it denotes when quoted
+  pub fn to_datum(&self) -> Result<Arc<Type>, SyntaxError> {
+    Ok(match self {
+      Token::Word(s, _) =>
+        literal(self, s)?.unwrap_or_else(|| Arc::new(Type::Symbol(s.to_string()))),
+      Token::Str(s, _) => Arc::new(Type::Str(s.to_string())),
+      Token::Group(g, _) => match dotted(g) {
+        Some((a, b)) => Arc::new(Type::Tuple(a.to_datum()?, b.to_datum()?)),
+        None => Type::new_list(datums(g)?),
+      },
+      Token::Braced(brace, items, _) => {
+        // checked first, so that each key of a map has a value
+        collection(*brace, items)?;
+        let items = datums(items)?;
+        Arc::new(match brace {
+          Brace::Map => Type::Map(map_from(&items).expect("Each key has a value")),
+          Brace::Set => Type::Set(set_from(&items)),
+        })
+      },
+    })
+  }
+  pub fn to_ast(&self) -> Result<Expr, SyntaxError> {
+    Ok(match self {
+      Token::Word(s, _) => match &s[..] {
+        s if is_reserved(s) => return SyntaxError::at(self, "Reserved keyword used"),
+        s => match literal(self, s)? {
+          Some(v) => Expr::Value(v),
+          None => Expr::Variable(s.to_string()),
+        },
       },
-      Token::Group(ref g) if g.len() == 0 => Expr::Value(Type::unit()),
-      Token::Group(ref g) => if let Token::Word(ref s) = g[0] {
+      Token::Str(s, _) => Expr::Value(Arc::new(Type::Str(s.to_string()))),
+      // built by the function itself rather than whatever hash-map or hash-set are bound to
+      Token::Braced(brace, items, _) => Expr::Call(
+        Arc::new(Expr::Value(Type::new_rust_closure(collection(*brace, items)?))),
+        sub_asts(items)?),
+      Token::Group(ref g, _) if g.len() == 0 => Expr::Value(Type::unit()),
+      Token::Group(ref g, _) => if let Token::Word(ref s, _) = g[0] {
         match &s[..] {
+          "quote" | "quasiquote" | "unquote" | "unquote-splicing" => match quote_form(g) {
+            Some(("quote", datum)) => Expr::Value(datum.to_datum()?),
+            Some(("quasiquote", datum)) => quasiquote(datum, 0)?,
+            Some(_) => return SyntaxError::at(self, &format!("{} used outside of quasiquote", s)),
+            None => return SyntaxError::at(self, &format!("{} takes exactly one operand", s)),
+          },
+          "let" if g.len() > 2 && is_group(&g[1]) => {
+            let (names, body) = hidden_names(bindings(&g[1])?, "bind", body(self, &g[2..])?);
+            Expr::Assign(Assign::Locals(names, body))
+          },
+          // each value can see the names bound before it
+          "let*" if g.len() > 2 => {
+            let mut nested = body(self, &g[2..])?;
+            for (p, val) in bindings(&g[1])?.into_iter().rev() {
+              nested = Arc::new(pattern::destructure(p, (*val).clone(), nested));
+            }
+            (*nested).clone()
+          },
+          // letrec is evaluated like letrec*, as a letrec whose values use each other's values
+          // while they are being evaluated is an error anyways
+          "letrec" | "letrec*" if g.len() > 2 => Expr::Assign(Assign::Rec(
+            bindings(&g[1])?.into_iter().map(|(p, val)| match p {
+              Pattern::Bind(name) => Ok((name, val)),
+              _ => SyntaxError::at(&g[1], &format!("{} can only bind names", s)),
+            }).collect::<Result<_, _>>()?,
+            body(self, &g[2..])?)),
+          "let*" | "letrec" | "letrec*" =>
+            return SyntaxError::at(self, &format!("{} must have bindings and a body", s)),
           "let" => match g.len() {
-            4 => {
-              let bound_to = if let Some(Token::Word(s)) = g.get(1) { s }
-                else { panic!("Must assign to name") };
-              let a = Assign::Local(bound_to.to_string(), Arc::new(g[2].to_ast()),
-                Arc::new(g[3].to_ast()));
-              Expr::Assign(a)
-            },
-            3 => {
-              let bound_to = if let Some(Token::Word(s)) = g.get(1) { s }
-                else { panic!("Must assign to name") };
-              let a = Assign::Global(bound_to.to_string(), Arc::new(g[2].to_ast()));
-              Expr::Assign(a)
-            },
-            _ => panic!("Invalid let statement, must have 2-3 operands"),
+            3 => Expr::Assign(Assign::Global(name_of(g.get(1), self)?, Arc::new(g[2].to_ast()?))),
+            n if n > 3 => Expr::Assign(Assign::Local(name_of(g.get(1), self)?,
+              Arc::new(g[2].to_ast()?), body(self, &g[3..])?)),
+            _ => return SyntaxError::at(self, "Invalid let statement, must have a name and value"),
           },
           "defn" => {
-            let name = (if let Some(Token::Word(s)) = g.get(1) { s }
-              else { panic!("Must assign to name") }).to_string();
-            let body = Arc::new(g.last().expect("Defn must have body").to_ast());
-            Expr::Defn(Arc::new(Defn{
-              name,
-              params: g[0..(g.len()-1)].iter().skip(1).map(|it| match it {
-                Token::Word(s) if s.starts_with("&") => ParamType::Rest(s[1..].to_string()),
-                Token::Word(s) => ParamType::Singular(s.to_string()),
-                Token::Group(_) => panic!("Can only have string params"),
-              }).collect(),
-              body,
-            }))
+            if g.len() < 3 {
+              return SyntaxError::at(self, "Defn must have a name and body")
+            }
+            // the params are the words and patterns before the body, which starts at the first
+            // expression which is not one, or else is the last one
+            let start = g[2..(g.len()-1)].iter().position(|t| !is_param(t))
+              .map_or(g.len() - 1, |i| i + 2);
+            let (params, body) = pattern_params(&g[2..start], body(self, &g[start..])?)?;
+            Expr::Defn(Arc::new(Defn{name: name_of(g.get(1), self)?, params, body}))
+          },
+          // (lambda (params...) body...), an anonymous function
+          "lambda" | "fn" => match (g.get(1), g.len()) {
+            (Some(Token::Group(ps, _)), n) if n > 2 => {
+              let (params, body) = pattern_params(ps, body(self, &g[2..])?)?;
+              Expr::Defn(Arc::new(Defn{name: String::new(), params, body}))
+            },
+            (Some(ps @ Token::Word(..)), _) | (Some(ps @ Token::Str(..)), _) =>
+              return SyntaxError::at(ps, &format!("Parameters of {} must be a list", s)),
+            _ => return SyntaxError::at(self, &format!("{} must have parameters and a body", s)),
           },
-          "if" => Expr::If(Arc::new(g[1].to_ast()),
-          Arc::new(g[2].to_ast()), Arc::new(g[3].to_ast())),
-          func => Expr::Call(Arc::new(Expr::Variable(func.to_string())), g.iter().skip(1)
-            .map(|it| Arc::new(it.to_ast())).collect())
+          "set!" => match (g.get(1), g.len()) {
+            (Some(Token::Word(name, _)), 3) => Expr::Assign(Assign::Set(
+              Arc::new(Expr::Variable(name.to_string())), Arc::new(g[2].to_ast()?))),
+            _ => return SyntaxError::at(self, "Set! must have a name and a value"),
+          },
+          "if" if g.len() == 4 => Expr::If(Arc::new(g[1].to_ast()?),
+            Arc::new(g[2].to_ast()?), Arc::new(g[3].to_ast()?)),
+          "if" => return SyntaxError::at(self, "If must have a condition and two branches"),
+          "try" => try_form(self, g)?,
+          "match" => match_form(self, g)?,
+          "for" => for_form(self, g)?,
+          "begin" | "do" => Expr::Seq(sub_asts(&g[1..])?),
+          "cond" | "case" | "when" | "unless" | "and" | "or" => derived(self, s, &g[1..])?,
+          "defmacro" | "macroexpand" =>
+            return SyntaxError::at(self, &format!("{} must be macro expanded first", s)),
+          func => Expr::Call(Arc::new(Expr::Variable(func.to_string())), sub_asts(&g[1..])?),
         }
       } else {
-        Expr::Call(Arc::new(g[0].to_ast()), g.iter().skip(1).map(|it|
-          Arc::new(it.to_ast())).collect())
+        Expr::Call(Arc::new(g[0].to_ast()?), sub_asts(&g[1..])?)
       }
-    }
+    })
+  }
+}
+
+#[test]
+fn test_quote() {
+  use lisp_parse::parse;
+  use ast::Env;
+
+  let eval = |src: &str| {
+    let mut g_env = Env::default_global();
+    parse(String::from(src)).unwrap()[0].to_ast().unwrap().resolve(&mut g_env).unwrap()
+      .eval(Env::default(), &mut g_env).unwrap()
+  };
+  let expected = Type::new_list(vec!(Arc::new(Type::Symbol(String::from("a"))),
+    Type::new_number(1.0), Type::new_list(vec!(Arc::new(Type::Str(String::from("b")))))));
+  assert!(eval("'(a 1 (\"b\"))").to_type().equals(&expected));
+
+  let expected = Type::new_list(vec!(Type::new_number(1.0), Type::new_number(3.0),
+    Type::new_number(4.0), Type::new_number(5.0)));
+  assert!(eval("`(1 ,(+ 1 2) ,@(cons 4 5 []))").to_type().equals(&expected));
+
+  let nested = eval("`(1 `(2 ,(3 ,(+ 1 3))))");
+  let expected = eval("'(1 (quasiquote (2 (unquote (3 4)))))");
+  assert!(nested.to_type().equals(&expected.to_type()));
+}
+
+#[test]
+fn test_syntax_errors() {
+  use lisp_parse::parse;
+
+  let err = parse(String::from("(+ 1\n  (if t 2))")).unwrap()[0].to_ast().unwrap_err();
+  assert_eq!((err.span.line, err.span.col), (2, 3));
+  let err = parse(String::from("(let \"x\" 1 x)")).unwrap()[0].to_ast().unwrap_err();
+  assert_eq!(err.span.col, 6);
+  let err = parse(String::from("(let ((x 1) y) x)")).unwrap()[0].to_ast().unwrap_err();
+  assert_eq!(err.span.col, 13);
+  let err = parse(String::from("(match x (_ 1)\n  ((a . b) 2))")).unwrap()[0].to_ast().unwrap_err();
+  assert_eq!((err.span.line, err.span.col), (2, 3));
+  let err = parse(String::from("(match x ((1 a) 1) ((1 2) 2))")).unwrap()[0].to_ast().unwrap_err();
+  assert_eq!(err.span.col, 20);
+  let err = parse(String::from("(lambda x x)")).unwrap()[0].to_ast().unwrap_err();
+  assert_eq!(err.span.col, 9);
+  let err = parse(String::from("(for ((x xs)) (when) x)")).unwrap()[0].to_ast().unwrap_err();
+  assert_eq!(err.span.col, 15);
+  for (src, col) in vec!(("(list {1 2 3})", 12), ("'{a}", 3), ("`(x {,x})", 6)) {
+    let err = parse(String::from(src)).unwrap()[0].to_ast().unwrap_err();
+    assert_eq!((&err.message[..], err.span.col), ("Expected a value for each key of the map", col),
+      "{}", src);
+  }
+  for (src, col) in vec!(("(defn f x 1 2)", 11), ("(lambda (a t) a)", 12), ("(defn f &[] 1)", 9)) {
+    let err = parse(String::from(src)).unwrap()[0].to_ast().unwrap_err();
+    assert!(err.message.ends_with("cannot be the name of a param"), "{}", src);
+    assert_eq!(err.span.col, col, "{}", src);
+  }
+  let bad_chars = vec!(("(list #\\bogus)", 7), ("'(a #\\ab)", 5), ("#\\", 1),
+    ("(match c (#\\x 1) (#\\ox 2))", 19));
+  for (src, col) in bad_chars {
+    let err = parse(String::from(src)).unwrap()[0].to_ast().unwrap_err();
+    assert_eq!((&err.message[..], err.span.col), ("Unknown character name", col), "{}", src);
+  }
+}
+
+#[test]
+fn test_try() {
+  use lisp_parse::parse;
+  use ast::Env;
+  use error::RuntimeError;
+
+  let mut g_env = Env::default_global();
+  let mut eval = |src: &str| parse(String::from(src)).unwrap()[0].to_ast().unwrap()
+    .resolve(&mut g_env).and_then(|e| e.eval(Env::default(), &mut g_env)).map(|v| v.to_type());
+
+  let caught = eval("(try (raise (error \"bad\" 3)) (catch e (hd (error-payload e))))").unwrap();
+  assert!(caught.equals(&Type::new_number(3.0)));
+  let caught = eval("(try (+ 1 \"a\") (catch e (error-payload e)))").unwrap();
+  assert!(caught.equals(&Arc::new(Type::Symbol(String::from("type-mismatch")))));
+  let caught = eval("(try (raise 5) (catch e (+ e 1)))").unwrap();
+  assert!(caught.equals(&Type::new_number(6.0)));
+
+  eval("(let cleaned f)").unwrap();
+  match eval("(try (raise 1) (finally (let cleaned t)))") {
+    Err(RuntimeError::Raised(ref v)) if v.equals(&Type::new_number(1.0)) => (),
+    other => panic!("Expected the raised value to propagate, got {:?}", other),
   }
+  assert!(eval("cleaned").unwrap().equals(&Arc::new(Type::Bool(true))));
+  assert!(eval("(try 1 (catch e 2) (finally 3))").unwrap().equals(&Type::new_number(1.0)));
 }
@@ -0,0 +1,221 @@
+use ast::{Expr, Assign, Defn, ParamType, Type};
+use error::Arity;
+use std::sync::Arc;
+use std::borrow::Borrow;
+
+#[derive(Debug, Clone, Copy)]
+pub enum Handler {
+  // Binds the error and continues at the handler
+  Catch,
+  // Holds on to the error until a Reraise after the cleanup
+  Finally,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum Op {
+  // Pushes the constant at the index
+  Const(usize),
+  // Pushes the variable in the frame that many frames up, at that slot within the frame
+  Local(usize, usize),
+  // Pushes the global in the slot
+  Global(usize),
+  // Pops a value into the global named by the name at the index, then pushes unit
+  DefineGlobal(usize),
+  // Pops a value into the global in the slot, which must already have a value
+  SetGlobal(usize),
+  // Pushes a closure of the function at the index over the current frame
+  Closure(usize),
+  // Calls the function below the given number of arguments
+  Call(usize),
+  // Calls a function in place of the current one, must be followed by a return
+  TailCall(usize),
+  Return,
+  Jump(usize),
+  // Pops a value and jumps unless it is true
+  JumpUnless(usize),
+  // Pops that many values into a new frame
+  Bind(usize),
+  // Pushes a new frame with that many slots, which are unit until they are set
+  Reserve(usize),
+  // Pops a value into the variable in the frame that many frames up, at that slot
+  SetLocal(usize, usize),
+  // Drops the innermost frame
+  Unbind,
+  Pop,
+  // Jumps to the target if an error is raised before the matching PopHandler
+  PushHandler(usize, Handler),
+  PopHandler,
+  // Raises the most recent error held for a finally clause
+  Reraise,
+}
+
+// A compiled function. Its frame holds the function itself followed by its parameters.
+#[derive(Debug)]
+pub struct Proto {
+  pub name: String,
+  pub params: Vec<ParamType>,
+  pub arity: Arity,
+  pub code: Vec<Op>,
+  pub constants: Vec<Arc<Type>>,
+  pub names: Vec<String>,
+  pub functions: Vec<Arc<Proto>>,
+}
+
+// Compiles resolved expressions, so that variables already refer to their frame and slot
+struct Compiler {
+  proto: Proto,
+}
+
+impl Compiler {
+  fn emit(&mut self, op: Op) -> usize {
+    self.proto.code.push(op);
+    self.proto.code.len() - 1
+  }
+  // Points the jump at the index to the next op emitted
+  fn patch(&mut self, at: usize) {
+    let target = self.proto.code.len();
+    self.proto.code[at] = match self.proto.code[at] {
+      Op::Jump(_) => Op::Jump(target),
+      Op::JumpUnless(_) => Op::JumpUnless(target),
+      Op::PushHandler(_, kind) => Op::PushHandler(target, kind),
+      op => panic!("Cannot patch {:?}", op),
+    };
+  }
+  fn name(&mut self, name: &str) -> usize {
+    match self.proto.names.iter().position(|n| n == name) {
+      Some(i) => i,
+      None => {
+        self.proto.names.push(name.to_string());
+        self.proto.names.len() - 1
+      },
+    }
+  }
+  // Compiles the body of a binding form, whose frame has just been pushed
+  fn bound(&mut self, body: &Expr, tail: bool) {
+    self.compile(body, tail);
+    // in tail position the frame is dropped along with the function's
+    if !tail {
+      self.emit(Op::Unbind);
+    }
+  }
+  fn compile(&mut self, expr: &Expr, tail: bool) {
+    match expr {
+      Expr::Value(v) => {
+        self.proto.constants.push(Arc::clone(v));
+        let i = self.proto.constants.len() - 1;
+        self.emit(Op::Const(i));
+      },
+      Expr::Variable(name) => panic!("Variable {} was not resolved before compiling", name),
+      Expr::Local(depth, i) => { self.emit(Op::Local(*depth, *i)); },
+      Expr::Global(i) => { self.emit(Op::Global(*i)); },
+      Expr::Defn(defn) => {
+        let proto = function(defn);
+        self.proto.functions.push(Arc::new(proto));
+        let i = self.proto.functions.len() - 1;
+        self.emit(Op::Closure(i));
+      },
+      Expr::Call(operator, operands) => {
+        self.compile(operator, false);
+        operands.iter().for_each(|o| self.compile(o, false));
+        self.emit(if tail { Op::TailCall(operands.len()) } else { Op::Call(operands.len()) });
+      },
+      Expr::Assign(Assign::Local(_, val, body)) => {
+        self.compile(val, false);
+        self.emit(Op::Bind(1));
+        self.bound(body, tail);
+      },
+      Expr::Assign(Assign::Locals(bindings, body)) => {
+        bindings.iter().for_each(|(_, val)| self.compile(val, false));
+        self.emit(Op::Bind(bindings.len()));
+        self.bound(body, tail);
+      },
+      Expr::Assign(Assign::Set(target, val)) => {
+        self.compile(val, false);
+        match target.borrow() {
+          Expr::Local(depth, i) => self.emit(Op::SetLocal(*depth, *i)),
+          Expr::Global(i) => self.emit(Op::SetGlobal(*i)),
+          target => panic!("Cannot assign to {:?}", target),
+        };
+        self.compile(&Expr::Value(Type::unit()), tail);
+      },
+      Expr::Assign(Assign::Rec(bindings, body)) => {
+        self.emit(Op::Reserve(bindings.len()));
+        for (i, (_, val)) in bindings.iter().enumerate() {
+          self.compile(val, false);
+          self.emit(Op::SetLocal(0, i));
+        }
+        self.bound(body, tail);
+      },
+      Expr::Assign(Assign::Global(name, val)) => {
+        self.compile(val, false);
+        let i = self.name(name);
+        self.emit(Op::DefineGlobal(i));
+      },
+      Expr::If(cond, pred, fallback) => {
+        self.compile(cond, false);
+        let to_fallback = self.emit(Op::JumpUnless(0));
+        self.compile(pred, tail);
+        let to_end = self.emit(Op::Jump(0));
+        self.patch(to_fallback);
+        self.compile(fallback, tail);
+        self.patch(to_end);
+      },
+      Expr::Seq(exprs) => match exprs.split_last() {
+        Some((last, init)) => {
+          for e in init {
+            self.compile(e, false);
+            self.emit(Op::Pop);
+          }
+          self.compile(last, tail);
+        },
+        None => self.compile(&Expr::Value(Type::unit()), tail),
+      },
+      Expr::Try(t) => {
+        let finally = t.finally.as_ref().map(|_| self.emit(Op::PushHandler(0, Handler::Finally)));
+        let catch = t.catch.as_ref().map(|_| self.emit(Op::PushHandler(0, Handler::Catch)));
+        self.compile(&t.body, false);
+        if let (Some(at), Some((_, handler))) = (catch, &t.catch) {
+          self.emit(Op::PopHandler);
+          let to_end = self.emit(Op::Jump(0));
+          self.patch(at);
+          self.emit(Op::Bind(1));
+          self.compile(handler, false);
+          self.emit(Op::Unbind);
+          self.patch(to_end);
+        }
+        if let (Some(at), Some(cleanup)) = (finally, &t.finally) {
+          self.emit(Op::PopHandler);
+          self.compile(cleanup, false);
+          self.emit(Op::Pop);
+          let to_end = self.emit(Op::Jump(0));
+          self.patch(at);
+          self.compile(cleanup, false);
+          self.emit(Op::Pop);
+          self.emit(Op::Reraise);
+          self.patch(to_end);
+        }
+      },
+    }
+  }
+}
+
+fn function(defn: &Defn) -> Proto {
+  let proto = Proto{
+    name: defn.name.to_string(),
+    params: defn.params.clone(),
+    arity: defn.arity(),
+    code: Vec::new(),
+    constants: Vec::new(),
+    names: Vec::new(),
+    functions: Vec::new(),
+  };
+  let mut c = Compiler{proto};
+  c.compile(&defn.body, true);
+  c.emit(Op::Return);
+  c.proto
+}
+
+// Compiles a resolved top level expression into a function of no arguments
+pub fn compile(expr: &Expr) -> Proto {
+  function(&Defn{name: String::new(), params: Vec::new(), body: Arc::new(expr.clone())})
+}
@@ -0,0 +1,206 @@
+use ast::{Expr, GlobalEnv, ParamType, Type};
+use error::RuntimeError;
+use vm::compile::{Handler, Op, Proto};
+use std::sync::{Arc, RwLock};
+use std::borrow::Borrow;
+
+// The variables bound by a function call or let, linked to the frames enclosing it
+#[derive(Debug)]
+pub struct Frame {
+  slots: RwLock<Vec<Arc<Type>>>,
+  parent: Option<Arc<Frame>>,
+}
+
+#[derive(Debug)]
+pub struct Closure {
+  pub proto: Arc<Proto>,
+  frame: Option<Arc<Frame>>,
+}
+
+impl Closure {
+  fn bind(&self, func: &Arc<Type>, args: Vec<Arc<Type>>) -> Result<Arc<Frame>, RuntimeError> {
+    self.proto.arity.check(args.len())?;
+    let mut slots = Vec::with_capacity(self.proto.params.len() + 1);
+    slots.push(Arc::clone(func));
+    let mut args = args.into_iter();
+    for p in self.proto.params.iter() {
+      slots.push(match p {
+        ParamType::Singular(_) => args.next().unwrap(),
+        ParamType::Rest(_) => Type::new_list(args.by_ref().collect()),
+      });
+    }
+    Ok(Arc::new(Frame{slots: RwLock::new(slots), parent: self.frame.clone()}))
+  }
+}
+
+struct Call {
+  proto: Arc<Proto>,
+  ip: usize,
+  frame: Option<Arc<Frame>>,
+}
+
+struct HandlerFrame {
+  target: usize,
+  kind: Handler,
+  // how deep the call and value stacks were when the handler was pushed
+  calls: usize,
+  stack: usize,
+  frame: Option<Arc<Frame>>,
+}
+
+struct Machine {
+  stack: Vec<Arc<Type>>,
+  calls: Vec<Call>,
+  handlers: Vec<HandlerFrame>,
+  // errors waiting for a finally clause to finish before being raised again
+  pending: Vec<RuntimeError>,
+}
+
+impl Machine {
+  fn run(&mut self, g_env: &mut GlobalEnv) -> Result<Arc<Type>, RuntimeError> {
+    loop {
+      match self.execute(g_env) {
+        Ok(v) => return Ok(v),
+        Err(e) => self.unwind(e)?,
+      }
+    }
+  }
+  // Moves control to the innermost handler, or gives the error back if there is none
+  fn unwind(&mut self, e: RuntimeError) -> Result<(), RuntimeError> {
+    let handler = self.handlers.pop().ok_or_else(|| e.clone())?;
+    self.calls.truncate(handler.calls);
+    self.stack.truncate(handler.stack);
+    let call = self.calls.last_mut().unwrap();
+    call.ip = handler.target;
+    call.frame = handler.frame;
+    match handler.kind {
+      Handler::Catch => self.stack.push(e.to_value()),
+      Handler::Finally => self.pending.push(e),
+    };
+    Ok(())
+  }
+  fn execute(&mut self, g_env: &mut GlobalEnv) -> Result<Arc<Type>, RuntimeError> {
+    loop {
+      let op = {
+        let call = self.calls.last_mut().unwrap();
+        call.ip += 1;
+        call.proto.code[call.ip - 1]
+      };
+      match op {
+        Op::Const(i) => {
+          let v = Arc::clone(&self.call().proto.constants[i]);
+          self.stack.push(v);
+        },
+        Op::Local(depth, i) => {
+          let mut frame = self.call().frame.as_ref().unwrap();
+          for _ in 0..depth {
+            frame = frame.parent.as_ref().unwrap();
+          }
+          let v = Arc::clone(&frame.slots.read().unwrap()[i]);
+          self.stack.push(v);
+        },
+        Op::Global(i) => self.stack.push(g_env.get_slot(i)?),
+        Op::SetGlobal(i) => {
+          let v = self.stack.pop().unwrap();
+          g_env.set_slot(i, v)?;
+        },
+        Op::DefineGlobal(i) => {
+          let v = self.stack.pop().unwrap();
+          g_env.define(&self.call().proto.names[i], v);
+          self.stack.push(Type::unit());
+        },
+        Op::Closure(i) => {
+          let closure = {
+            let call = self.call();
+            Closure{proto: Arc::clone(&call.proto.functions[i]), frame: call.frame.clone()}
+          };
+          self.stack.push(Arc::new(Type::VmClosure(Arc::new(closure))));
+        },
+        Op::Call(argc) => self.call_with(argc, false, g_env)?,
+        Op::TailCall(argc) => self.call_with(argc, true, g_env)?,
+        Op::Return => {
+          self.calls.pop();
+          if self.calls.is_empty() {
+            return Ok(self.stack.pop().unwrap())
+          }
+        },
+        Op::Jump(target) => self.calls.last_mut().unwrap().ip = target,
+        Op::JumpUnless(target) => match self.stack.pop().unwrap().borrow() {
+          Type::Bool(true) => (),
+          _ => self.calls.last_mut().unwrap().ip = target,
+        },
+        Op::Bind(n) => {
+          let slots = self.stack.split_off(self.stack.len() - n);
+          self.push_frame(slots);
+        },
+        Op::Reserve(n) => self.push_frame(vec!(Type::unit(); n)),
+        Op::SetLocal(depth, i) => {
+          let v = self.stack.pop().unwrap();
+          let mut frame = self.call().frame.as_ref().unwrap();
+          for _ in 0..depth {
+            frame = frame.parent.as_ref().unwrap();
+          }
+          frame.slots.write().unwrap()[i] = v;
+        },
+        Op::Unbind => {
+          let call = self.calls.last_mut().unwrap();
+          call.frame = call.frame.take().and_then(|f| f.parent.clone());
+        },
+        Op::Pop => { self.stack.pop(); },
+        Op::PushHandler(target, kind) => {
+          let handler = HandlerFrame{target, kind, calls: self.calls.len(),
+            stack: self.stack.len(), frame: self.call().frame.clone()};
+          self.handlers.push(handler);
+        },
+        Op::PopHandler => { self.handlers.pop(); },
+        Op::Reraise => return Err(self.pending.pop().unwrap()),
+      }
+    }
+  }
+  fn push_frame(&mut self, slots: Vec<Arc<Type>>) {
+    let call = self.calls.last_mut().unwrap();
+    call.frame = Some(Arc::new(Frame{slots: RwLock::new(slots), parent: call.frame.take()}));
+  }
+  fn call(&self) -> &Call {
+    self.calls.last().unwrap()
+  }
+  fn call_with(&mut self, argc: usize, tail: bool, g_env: &mut GlobalEnv)
+    -> Result<(), RuntimeError> {
+    let args = self.stack.split_off(self.stack.len() - argc);
+    let func = self.stack.pop().unwrap();
+    match func.borrow() {
+      Type::VmClosure(c) => {
+        let call = Call{proto: Arc::clone(&c.proto), ip: 0, frame: Some(c.bind(&func, args)?)};
+        if tail {
+          *self.calls.last_mut().unwrap() = call;
+        } else {
+          self.calls.push(call);
+        }
+      },
+      Type::RustClosure(f, arity) => {
+        arity.check(args.len())?;
+        self.stack.push(f(args, g_env)?)
+      },
+      _ => self.stack.push(Expr::apply(&func, args, g_env)?),
+    };
+    Ok(())
+  }
+}
+
+fn start(call: Call) -> Machine {
+  Machine{stack: Vec::new(), calls: vec!(call), handlers: Vec::new(), pending: Vec::new()}
+}
+
+// Resolves, compiles and runs a top level expression
+pub fn eval(expr: &Expr, g_env: &mut GlobalEnv) -> Result<Arc<Type>, RuntimeError> {
+  let proto = Arc::new(::vm::compile::compile(&expr.resolve(g_env)?));
+  let frame = Some(Arc::new(Frame{slots: RwLock::new(vec!(Type::unit())), parent: None}));
+  start(Call{proto, ip: 0, frame}).run(g_env)
+}
+
+// Calls a compiled closure from outside of the machine
+pub fn call(c: &Closure, func: &Arc<Type>, args: Vec<Arc<Type>>, g_env: &mut GlobalEnv)
+  -> Result<Arc<Type>, RuntimeError> {
+  let call = Call{proto: Arc::clone(&c.proto), ip: 0, frame: Some(c.bind(func, args)?)};
+  start(call).run(g_env)
+}
@@ -0,0 +1,228 @@
+pub mod compile;
+pub mod machine;
+
+#[cfg(test)]
+mod tests {
+  use ast::{Env, Type};
+  use error::RuntimeError;
+  use lisp_parse::parse;
+  use vm::machine::eval;
+  use std::sync::Arc;
+
+  fn run_all(src: &str) -> Result<Arc<Type>, RuntimeError> {
+    let mut g_env = Env::default_global();
+    let mut last = Ok(Type::unit());
+    for token in parse(String::from(src)).unwrap() {
+      last = eval(&token.to_ast().unwrap(), &mut g_env);
+    }
+    last
+  }
+
+  // Each case is run by both the bytecode machine and the tree walking interpreter
+  fn cases() -> Vec<(&'static str, &'static str)> {
+    vec!(
+      ("(+ 1 2)", "3"),
+      ("(let x 2 (let y 3 (* x y)))", "6"),
+      ("(if (= 1 1) 'a 'b)", "'a"),
+      ("(let x 1 (if f x (let x 2 x)))", "2"),
+      ("(let f (defn f x &r (cons x r))) (f 1 2 3)", "'(1 2 3)"),
+      ("(let k (defn k x (defn g y x))) ((k 1) 2)", "1"),
+      ("(let n (defn n x (if (= x 0) 0 (n (- x 1))))) (n 50000)", "0"),
+      ("(let g (defn g x (+ x 1))) (let g2 (defn g2 x (g (g x)))) (g2 1)", "3"),
+      ("(try (+ 1 'a) (catch e (error-payload e)))", "'type-mismatch"),
+      ("(try (raise 1) (catch e (+ e 1)) (finally 5))", "2"),
+      ("(let x 1) (try (try (raise 2) (finally (let x 3))) (catch e (+ x e)))", "5"),
+      ("(let h (defn h x (raise x))) (try (+ 1 (h 4)) (catch e e))", "4"),
+      ("`(1 ,@(cons 2 3 []))", "'(1 2 3)"),
+      ("((lambda (x) (* x x)) 3)", "9"),
+      ("((fn (x &r) (cons x r)) 1 2 3)", "'(1 2 3)"),
+      ("(let adder (defn adder n (lambda (x) (+ x n)))) ((adder 2) 3)", "5"),
+      ("(cond ((= 1 2) 'a) ((= 1 1) 'b) (else 'c))", "'b"),
+      ("(cond (f 1))", "()"),
+      ("(case (+ 1 1) ((1 3) 'odd) ((2 4) 'even) (else 'big))", "'even"),
+      ("(let x 1 (case 'y (x 1) (y (+ x 1))))", "2"),
+      ("(and t (= 1 1) t)", "t"),
+      ("(and f (raise 1))", "f"),
+      ("(or f t (raise 1))", "t"),
+      ("(or)", "f"),
+      ("(when (= 1 1) 1)", "1"),
+      ("(unless t 1)", "()"),
+      ("(begin)", "()"),
+      ("(do (let a 1) (let b 2) (+ a b))", "3"),
+      ("(let f (defn f x (let y 1) (+ x y))) (f 1)", "2"),
+      ("(let x 1 (let x 2) x)", "1"),
+      ("((lambda (x) (debug x) x) 3)", "3"),
+      ("(let x 1 (let ((x 2) (y x)) (+ x y)))", "3"),
+      ("(let* ((x 1) (y (+ x 1))) (* x y))", "2"),
+      ("(letrec ((ev (lambda (n) (if (= n 0) t (od (- n 1))))) \
+        (od (lambda (n) (if (= n 0) f (ev (- n 1)))))) (ev 10))", "t"),
+      ("(letrec* ((a 1) (b (+ a 1))) b)", "2"),
+      ("(let x 1 (set! x 2) x)", "2"),
+      ("(let counter (let n 0 (lambda () (set! n (+ n 1)) n))) (counter) (counter)", "2"),
+      ("(let g 1) (set! g 5) g", "5"),
+      ("(let b (box 1)) (set-box! b (+ (unbox b) 1)) (unbox b)", "2"),
+      ("(match '(1 2 3) (() 'empty) ((a) a) ((a b . rest) (+ a b)))", "3"),
+      ("(match 2 (1 'one) ('x 'x) (\"a\" 'a) (n if (= n 3) 'three) (_ 'other))", "'other"),
+      ("(let x 5 (match 1 (x if f x) (_ x)))", "5"),
+      ("(let* (((a . b) '(1 2)) (c (hd b))) (+ a c))", "3"),
+      ("(let (((a b) '(1 2)) (c 3)) (+ a b c))", "6"),
+      ("((lambda ((a . _) &r) (cons a r)) '(1 2) 3)", "'(1 3)"),
+      ("(let g (defn g (a . b) (h . r) (+ a (length b) h (length r)))) (g '(1 2) '(3 4 5))",
+        "7"),
+      ("(let f (defn f (a b) (+ a b))) (let g (defn g x (c) (let y 1) (+ x c y)))
+        (+ (f '(1 2)) (g 1 '(2)))", "7"),
+      ("(match (try (match 1 (2 2)) (catch e (error-message e))) (\"No pattern matched\" t))",
+        "t"),
+      ("(let n (defn n x (begin (debug x) (if (= x 0) 0 (n (- x 1)))))) (n 3)", "0"),
+      ("(cons (- 3) (/ 4) (/ 12 3 2) (modulo -7 2) (max 1 3 2) (floor (sqrt 10)) [])",
+        "'(-3 1/4 2 1 3 3.0)"),
+      ("(cons (* 4294967296 4294967296) (+ 1/3 (/ 2 3)) (exact->inexact 1/4) #x1F 1e3 [])",
+        "'(18446744073709551616 1 0.25 31 1000.0)"),
+      ("(list (range 0 1 0.25) (range 5 0 -2) (range 1 0 1/2) (range 0 1 -1) (range 1 3 2/3))",
+        "'((0 0.25 0.5 0.75) (5 3 1) () () (1 5/3 7/3))"),
+      ("(list (take '(a b) 5) (drop '(a b) 5) (take '(a b) 0))", "'((a b) () ())"),
+      ("(map (lambda (x y) (* x y)) (range 1 4) '(4 5 6 7))", "'(4 10 18)"),
+      ("(foldl - 0 (filter (fn (x) (> x 2)) (list 1 2 3 4)))", "-7"),
+      ("(foldr cons [] (append (reverse '(2 1)) (drop (take (range 10 0 -1) 3) 1)))",
+        "'(1 2 9 8)"),
+      ("(list (length '(a b)) (nth '(a b c) 2) (any error? '(1 2)) (all (fn (x) (< x 3)) '(1 2)))",
+        "'(2 c f t)"),
+      ("(list (sort '(3 1 2)) (sort '(\"b\" \"a\")) (sort '(1 3 2) (lambda (a b) (> a b))))",
+        "'((1 2 3) (\"a\" \"b\") (3 2 1))"),
+      ("(list (assoc 'b '((a 1) (b 2))) (assoc 'c (zip '(a b) '(1 2))))", "'((b 2) f)"),
+      ("(try (nth '(1) 1) (catch e (error-payload e)))", "'index-out-of-range"),
+      ("(for ((x (range 3)) (y (range x 3))) (when (< x y)) (list x y))",
+        "'((0 1) (0 2) (1 2))"),
+      ("(for ((x '(1 2 3 4)) (unless (= x 2)) (let y (* x x))) (when (> y 4)) y)", "'(9 16)"),
+      ("(for (((a b) '((1 2) 3 (4 5)))) (+ a b))", "'(3 9)"),
+      ("(let xs (range 3) (for ((n xs) (m (range n))) m))", "'(0 0 1)"),
+      ("(list (substring \"h\u{e9}llo\" 1 3) (substring \"hello\" 2) (substring \"hello\" 2 2))",
+        "'(\"\u{e9}l\" \"llo\" \"\")"),
+      ("(format \"~a and ~s~%~~\" \"x\" (list \"y\" 1/2 'z))", "\"x and (\\\"y\\\" 1/2 z)\\n~\""),
+      ("(list (string-append \"ab\" \"c\") (substring \"hello\" 1 3) (string-length \"h\u{e9}\"))",
+        "'(\"abc\" \"el\" 2)"),
+      ("(string-join (map string-upcase (string-split \" a  b \")) \"-\")", "\"A-B\""),
+      ("(list (string->number \"#x10\") (string->number \"x\") (number->string 1.5))",
+        "'(16 f \"1.5\")"),
+      ("(list (string-downcase \"AbC\") (string-split \"a,,b\" \",\"))",
+        "'(\"abc\" (\"a\" \"\" \"b\"))"),
+      ("(try (format \"~a\") (catch e (error-payload e)))", "'arity"),
+      ("(list #\\a #\\( #\\space #\\x41 (char->integer #\\newline) (integer->char 955))",
+        "'(#\\a #\\( #\\  #\\A 10 #\\\u{3bb})"),
+      ("(list->string (map char-upcase (filter char-alphabetic? (string->list \"a1 b\"))))",
+        "\"AB\""),
+      ("(match #\\b (#\\a 1) (#\\b 2))", "2"),
+      ("(format \"~a~s\" #\\x #\\x)", "\"x#\\\\x\""),
+      ("(and (< 1 2 3) (>= 3 3 1) (<= 1 1 2))", "t"),
+      ("(try (/ 1 0) (catch e (error-payload e)))", "'divide-by-zero"),
+      ("(map type-of (list 1 \"a\" #\\a 'b t [] + (box 1)))",
+        "'(number string char symbol bool list function box)"),
+      ("(list (integer? 2.0) (fixnum? 2.0) (rational? 1/2) (rational? +inf.0) (null? ()))",
+        "'(t f t f t)"),
+      ("(list (null? (list 1)) (pair? (list 1)) (procedure? map) (procedure? 'map))",
+        "'(f t t f)"),
+      ("(list (pair? (pair 1 2)) (pair? '(1 . 2)) (pair? ()) (pair? []) (pair? 1))",
+        "'(t t f f f)"),
+      ("(let g (defn g x &r x)) (map procedure-arity (list g (lambda () 1) substring))",
+        "'((at-least 1) 0 (between 2 3))"),
+      ("(try (substring) (catch e (error-payload e)))", "'arity"),
+      ("(let p (pair 1 \"a\")) (list (fst p) (snd p) (tuple? p) (= p '(1 . \"a\")))",
+        "'(1 \"a\" t t)"),
+      ("(match '(1 . (2 . 3)) ((tuple a (pair b c)) (+ a b c)))", "6"),
+      ("(match (pair 1 '(2 3)) ((tuple a (b . c)) (cons a (cons b c))))", "'(1 2 3)"),
+      ("(match '(1 . 2) ((a . b) 'list) ((pair a b) (+ a b)))", "3"),
+      ("(let x 2) `((a . ,x) ,(tuple 'b (+ x 1)))", "'((a . 2) (b . 3))"),
+      ("(map snd (zip '(1 2) '(3 4)))", "'(3 4)"),
+      ("(let m {'a 1 'b (+ 1 1)}) (list (get m 'b) (get m 'c) (get m 'c 0) (contains? m 'a))",
+        "'(2 f 0 t)"),
+      ("(let m (assoc {1 'x} 2 'y 1.0 'z)) (list (dissoc m 2) (= m {2 'y 1 'z}) (map? m))",
+        "'({1 z} t t)"),
+      ("(sort (keys (merge #{1 2} #{2 3} (dissoc #{4 5} 5))))", "'(1 2 3 4)"),
+      ("(list (foldl + 0 (vals {'a 1 'b 2})) (length {}) (get '{a #{b}} 'a))", "'(3 0 #{b})"),
+      ("(let x 2) (sort (for (((tuple k v) `{a ,x b ,(+ x 1)})) v))", "'(2 3)"),
+      ("(try (hash-map 1 2 3) (catch e (error-payload e)))", "3"),
+      ("(list (try (assoc {1 2} 3) (catch e (error-payload e))) (assoc 'b '((a . 1) (b . 2))))",
+        "'(3 (b . 2))"),
+      ("(list (assoc {1 2} '(({1 2} . x))) (assoc {1 2} '(a) 'b))", "'(({1 2} . x) {1 2 (a) b})"),
+      ("(let hash-map list) (list (get {1 2} 1) (length '(hash-map 1 2)) (length '(hash-map a)))",
+        "'(2 3 2)"),
+      ("(let x 2) (get (hd `({a ,(+ x 1)})) 'a)", "3"),
+      ("(list (length #{1 2}) (foldl + 0 (map snd {1 2 3 4})) (filter (lambda (x) (> x 1)) #{1}))",
+        "'(2 6 ())"),
+      ("(list (dissoc {1 'a 2 'b 3 'c} 1 3 4) (dissoc #{1 2 3} 2.0 5) (dissoc {1 2}))",
+        "(list {2 'b} #{1 3} {1 2})"),
+      ("(list (= (dissoc {1 'a} 1) (dissoc #{1} 1)) (merge {1 'a 2 'b} {2 'c} {3 'd}))",
+        "(list f {1 'a 2 'c 3 'd})"),
+      ("(list (get #{'a} 'a) (get #{'a} 'b) (contains? {1 f} 1) (sort (keys #{3 1 2})))",
+        "'(a f t (1 2 3))"),
+    )
+  }
+
+  #[test]
+  fn matches_tree_walker() {
+    for (src, expected) in cases() {
+      let expected = run_all(expected).unwrap();
+      let got = run_all(src).unwrap_or_else(|e| panic!("{} failed with {}", src, e));
+      assert!(got.equals(&expected), "{}: expected {:?}, got {:?}", src, expected, got);
+
+      let mut g_env = Env::default_global();
+      let mut tree = Type::unit();
+      for token in parse(String::from(src)).unwrap() {
+        tree = token.to_ast().unwrap().resolve(&mut g_env).unwrap()
+          .eval(Env::default(), &mut g_env).unwrap().to_type();
+      }
+      assert!(tree.equals(&expected), "{}: tree walker got {:?}", src, tree);
+    }
+  }
+
+  #[test]
+  fn errors() {
+    match run_all("(let f (defn f x x)) (f 1 2)") {
+      Err(RuntimeError::Arity{got: 2, ..}) => (),
+      other => panic!("Expected arity error, got {:?}", other),
+    }
+    match run_all("(try (raise 1) (finally 2))") {
+      Err(RuntimeError::Raised(_)) => (),
+      other => panic!("Expected raised error, got {:?}", other),
+    }
+    match run_all("(nth '(a b) 2)") {
+      Err(RuntimeError::IndexOutOfRange{index: 2, length: 2}) => (),
+      other => panic!("Expected an index out of range, got {:?}", other),
+    }
+    match run_all("(range 0 1 0.0)") {
+      Err(RuntimeError::TypeMismatch{expected: "nonzero step", ..}) => (),
+      other => panic!("Expected a step of zero to be an error, got {:?}", other),
+    }
+    match run_all("(substring \"hello\" 3 2)") {
+      Err(RuntimeError::IndexOutOfRange{index: 3, length: 2}) => (),
+      other => panic!("Expected the start to be past the end, got {:?}", other),
+    }
+    for src in &["(format \"~q\" 1)", "(format \"50~\")"] {
+      match run_all(src) {
+        Err(RuntimeError::Raised(_)) => (),
+        other => panic!("{}: expected a bad directive to be raised, got {:?}", src, other),
+      }
+    }
+    for src in &["(dissoc '(1 2) 1)", "(merge {1 'a} #{1})", "(vals #{1})"] {
+      match run_all(src) {
+        Err(RuntimeError::TypeMismatch{..}) => (),
+        other => panic!("{}: expected a type mismatch, got {:?}", src, other),
+      }
+    }
+    match run_all("(set! nope 1)") {
+      Err(RuntimeError::UnboundVariable(ref name)) if name == "nope" => (),
+      other => panic!("Expected nope to be unbound, got {:?}", other),
+    }
+    match run_all("(let f (defn f x (g x)))") {
+      Err(RuntimeError::UnboundVariable(ref name)) if name == "g" => (),
+      other => panic!("Expected g to be unbound, got {:?}", other),
+    }
+    // maps and sets have no order for these to go by
+    for src in &["(nth #{1} 0)", "(reverse {1 2})", "(sort #{2 1})", "(zip '(1) #{2})"] {
+      match run_all(src) {
+        Err(RuntimeError::TypeMismatch{expected: "list", ..}) => (),
+        other => panic!("{}: expected a list to be needed, got {:?}", src, other),
+      }
+    }
+  }
+}